@@ -0,0 +1,480 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use actix_web::{HttpRequest, HttpResponse, AsyncResponder, State, HttpMessage, ResponseError};
+use actix_web::http::header;
+use failure::Fail;
+use chrono::NaiveDateTime;
+use futures::future::{self, Future};
+
+use crate::utils::*;
+use crate::auth::{get_identity, get_viewer, get_share_query_token};
+use crate::common::{AppState, JobRunners};
+use crate::config::ThumbnailConfig;
+use crate::error::{GalleryError, GalleryInternalError};
+use crate::models::{AlbumTemplate, PhotoTemplate, Photo};
+use crate::models::album::{AccessGrant, PageRequest};
+use crate::models::helper::ExifExtractor;
+use crate::models::job::{Job, JobError, GetJobs, GetJob, GetJobErrors, CreateJob};
+use crate::models::photo_thumbnail::{PhotoThumbnail, ThumbnailSizes};
+use crate::models::search::{SearchQuery, SearchTemplate, SearchFilters};
+use crate::models::user::Identity;
+
+/// Error of the API, answered with a JSON body rather than a page.
+#[derive(Fail, Debug)]
+#[fail(display = "{}", _0)]
+pub struct ApiError(GalleryError);
+
+impl From<GalleryError> for ApiError {
+    fn from(error: GalleryError) -> Self {
+        ApiError(error)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: ErrorDetails,
+}
+
+#[derive(Serialize)]
+struct ErrorDetails {
+    code: &'static str,
+    message: String,
+}
+
+impl ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        let status = self.0.status_code();
+        // The details of the internal errors are only logged.
+        let message = if status.is_server_error() {
+            error!("API request failed, {}", self.0);
+            "Internal server error".to_string()
+        } else {
+            match self.0 {
+                GalleryError::InvalidForm(msg) => msg.to_string(),
+                GalleryError::AlbumNotFound { .. } => GalleryError::NotFound.to_string(),
+                GalleryError::ActixError(ref e) => e.to_string(),
+                ref e => e.to_string(),
+            }
+        };
+        HttpResponse::build(status).json(ErrorBody {
+            error: ErrorDetails { code: self.0.code(), message },
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct BreadcrumbEntry {
+    name: String,
+    path: String,
+}
+
+#[derive(Serialize)]
+struct AlbumResponse {
+    name: String,
+    path: String,
+    breadcrumb: Vec<BreadcrumbEntry>,
+    /// Sub-albums, only listed on the first page.
+    albums: Vec<AlbumEntry>,
+    photos: Vec<PhotoEntry>,
+    sort: &'static str,
+    page: i64,
+    page_count: i64,
+}
+
+#[derive(Serialize)]
+struct AlbumEntry {
+    name: String,
+    path: String,
+    /// Protected by a password the viewer didn't give yet.
+    is_locked: bool,
+}
+
+#[derive(Serialize)]
+struct PhotoEntry {
+    name: String,
+    path: String,
+    creation_date: Option<NaiveDateTime>,
+    mime_type: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    is_video: bool,
+    /// URLs of the thumbnails, by size.
+    thumbnails: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct PhotoResponse {
+    name: String,
+    path: String,
+    album_path: String,
+    breadcrumb: Vec<BreadcrumbEntry>,
+    /// Paths of the photos around this one in its album, in the order given
+    /// by the `sort` query parameter or the default one of the album.
+    previous_photo: Option<String>,
+    next_photo: Option<String>,
+    mime_type: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    duration: Option<f64>,
+    file_size: i64,
+    /// EXIF metadata, in the order displayed on the photo page.
+    metadata: Vec<MetadataEntry>,
+    thumbnails: BTreeMap<String, String>,
+    /// Missing when the original file can't be downloaded.
+    full_url: Option<String>,
+    variants: Vec<VariantEntry>,
+}
+
+#[derive(Serialize)]
+struct MetadataEntry {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct VariantEntry {
+    name: String,
+    mime_type: Option<String>,
+    file_size: i64,
+    full_url: String,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    photos: Vec<SearchEntry>,
+    total: i64,
+    sort: &'static str,
+    page: i64,
+    page_count: i64,
+    filters: SearchFilters,
+}
+
+#[derive(Serialize)]
+struct SearchEntry {
+    /// Path of the album holding the photo, linking to it.
+    album_path: String,
+    #[serde(flatten)]
+    photo: PhotoEntry,
+}
+
+#[derive(Serialize)]
+struct JobsResponse {
+    jobs: Vec<Job>,
+}
+
+#[derive(Serialize)]
+struct JobResponse {
+    #[serde(flatten)]
+    job: Job,
+    errors: Vec<JobError>,
+}
+
+#[derive(Deserialize)]
+struct JobRequest {
+    name: String,
+}
+
+/// Builds the URLs of the files of the photos, which carry the share link
+/// followed by the request so that they can be fetched without cookies.
+struct UrlBuilder {
+    thumbnails: Vec<(String, ThumbnailConfig)>,
+    cache_path: String,
+    share_token: Option<String>,
+}
+
+impl UrlBuilder {
+    fn new(req: &HttpRequest<AppState>) -> Self {
+        let config = &req.state().config;
+        UrlBuilder {
+            thumbnails: config.thumbnails.iter().map(|(size, thumbnail_config)| (size.clone(), thumbnail_config.clone())).collect(),
+            cache_path: config.cache_path.clone(),
+            share_token: get_share_query_token(req),
+        }
+    }
+
+    fn get_thumbnails(&self, path: &str, hash: &String) -> BTreeMap<String, String> {
+        self.thumbnails.iter()
+            .map(|(size, thumbnail_config)| {
+                let version = PhotoThumbnail::get_version(hash, thumbnail_config, self.cache_path.clone());
+                let mut url = format!("/{}/{}?v={}", encode_url_path(path), size, version);
+                if let Some(ref share_token) = self.share_token {
+                    url.push_str(&format!("&share={}", encode_url_path(share_token)));
+                }
+                (size.clone(), url)
+            })
+            .collect()
+    }
+
+    fn get_full_url(&self, path: &str) -> String {
+        let mut url = format!("/{}/full", encode_url_path(path));
+        if let Some(ref share_token) = self.share_token {
+            url.push_str(&format!("?share={}", encode_url_path(share_token)));
+        }
+        url
+    }
+}
+
+fn join_path(album_path: &str, name: &str) -> String {
+    if album_path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", album_path, name)
+    }
+}
+
+fn get_photo_entry(photo: PhotoThumbnail, album_path: &str, urls: &UrlBuilder) -> PhotoEntry {
+    let path = join_path(album_path, &photo.name);
+    PhotoEntry {
+        thumbnails: urls.get_thumbnails(&path, &photo.hash),
+        is_video: photo.is_video(),
+        path,
+        name: photo.name,
+        creation_date: photo.creation_date,
+        mime_type: photo.mime_type,
+        width: photo.width,
+        height: photo.height,
+    }
+}
+
+fn get_path_string(path: &Path) -> Result<String, GalleryError> {
+    path.to_str().map(str::to_string).ok_or(GalleryError::InvalidFileName)
+}
+
+fn get_breadcrumb(breadcrumb: Vec<(String, String)>) -> Vec<BreadcrumbEntry> {
+    breadcrumb.into_iter()
+        .map(|(url, name)| BreadcrumbEntry { name, path: url.trim_start_matches('/').to_string() })
+        .collect()
+}
+
+/// Jobs are managed by the administrators only. Authenticating with the
+/// session cookie is safe from cross-site requests as JSON bodies can't be
+/// sent by forms.
+fn require_admin(req: &HttpRequest<AppState>) -> impl Future<Item = Identity, Error = GalleryError> {
+    get_identity(req)
+        .and_then(|identity| {
+            match identity {
+                Some(identity) => if identity.is_admin() { Ok(identity) } else { Err(GalleryError::AdminRequired) },
+                None => Err(GalleryError::Unauthenticated),
+            }
+        })
+}
+
+pub fn get_album_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = ApiError>>
+{
+    let path: PathBuf = future_try!(req.match_info().query("path").map_err(|_| ApiError(GalleryError::NotFound)));
+    let album_path = future_try!(get_path_string(&path).map_err(ApiError));
+    let sizes = ThumbnailSizes::new(&state.config, true);
+    let page_request = PageRequest::from_query(&req.query(), state.config.albums.photos_per_page);
+    let urls = UrlBuilder::new(&req);
+
+    get_viewer(&req)
+        .and_then(move |viewer| AlbumTemplate::get(path, page_request, sizes, viewer, state.db.clone()))
+        .map(move |album| {
+            let albums = album.albums.into_iter()
+                .map(|child| AlbumEntry {
+                    path: join_path(&album_path, &child.name),
+                    name: child.name,
+                    is_locked: child.is_locked,
+                })
+                .collect();
+            let photos = album.photos.into_iter()
+                .map(|photo| get_photo_entry(photo, &album_path, &urls))
+                .collect();
+
+            HttpResponse::Ok().json(AlbumResponse {
+                name: album.name,
+                path: album_path,
+                breadcrumb: get_breadcrumb(album.breadcrumb),
+                albums,
+                photos,
+                sort: album.sort.as_param(),
+                page: album.pagination.page,
+                page_count: album.pagination.page_count,
+            })
+        })
+        .from_err()
+        .responder()
+}
+
+pub fn get_photo_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = ApiError>>
+{
+    // Segments refused by the router, such as hidden files, can't be photos.
+    let path: PathBuf = future_try!(req.match_info().query("path").map_err(|_| ApiError(GalleryError::NotFound)));
+    let name = future_try!(get_file_name_string(&path).map_err(ApiError));
+    let album_path = future_try!(path.parent().ok_or(GalleryError::NotFound).and_then(get_path_string).map_err(ApiError));
+    let sizes = ThumbnailSizes::new(&state.config, false);
+    let page_request = PageRequest::from_query(&req.query(), state.config.albums.photos_per_page);
+    let urls = UrlBuilder::new(&req);
+    let db_addr = state.db.clone();
+
+    // Photos are found the way the pages are, as the segment following the
+    // last album of the path.
+    get_viewer(&req)
+        .and_then(move |viewer| AlbumTemplate::get(path, page_request, sizes.clone(), viewer, db_addr.clone()).then(move |res| {
+            match res {
+                Err(GalleryError::AlbumNotFound { missing_segments: 1, last_album, current_breadcrumb, grant }) => {
+                    let res = PhotoTemplate::get(name, last_album, current_breadcrumb, page_request.sort, sizes, grant, db_addr)
+                        .map(move |photo| (photo, grant));
+                    Box::new(res) as Box<Future<Item = (PhotoTemplate, AccessGrant), Error = GalleryError>>
+                },
+                Err(e) => Box::new(future::err(e)),
+                Ok(_) => Box::new(future::err(GalleryError::NotFound)),
+            }
+        }))
+        .map(move |(photo, grant)| {
+            let PhotoTemplate { photo, breadcrumb, previous_photo, next_photo, variants, .. } = photo;
+            let path = join_path(&album_path, &photo.name);
+            let full_url = if grant.allows_download() || photo.is_video() {
+                Some(urls.get_full_url(&path))
+            } else {
+                None
+            };
+            let variants = variants.into_iter()
+                .map(|variant: Photo| VariantEntry {
+                    full_url: urls.get_full_url(&join_path(&album_path, &variant.name)),
+                    name: variant.name,
+                    mime_type: variant.mime_type,
+                    file_size: variant.file_size,
+                })
+                .collect();
+
+            HttpResponse::Ok().json(PhotoResponse {
+                metadata: photo.get_named_metadata().into_iter()
+                    .map(|(name, value)| MetadataEntry { name: name.to_string(), value })
+                    .collect(),
+                thumbnails: urls.get_thumbnails(&path, &photo.hash),
+                full_url,
+                previous_photo: previous_photo.map(|name| join_path(&album_path, &name)),
+                next_photo: next_photo.map(|name| join_path(&album_path, &name)),
+                breadcrumb: get_breadcrumb(breadcrumb),
+                name: photo.name,
+                path,
+                album_path,
+                mime_type: photo.mime_type,
+                width: photo.width,
+                height: photo.height,
+                duration: photo.duration,
+                file_size: photo.file_size,
+                variants,
+            })
+        })
+        .from_err()
+        .responder()
+}
+
+/// Takes the query parameters of the search page, a search without criteria
+/// only listing the filters.
+pub fn search_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = ApiError>>
+{
+    let query = SearchQuery::from_query(&req.query());
+    let page_request = PageRequest::from_query(&req.query(), state.config.albums.photos_per_page);
+    let sizes = ThumbnailSizes::new(&state.config, true);
+    let (gallery_name, db_addr) = (state.config.gallery_name.clone(), state.db.clone());
+    let urls = UrlBuilder::new(&req);
+
+    get_viewer(&req)
+        .and_then(move |viewer| SearchTemplate::get(query, page_request, gallery_name, sizes, viewer, db_addr))
+        .map(move |search| {
+            let photos = search.results.into_iter()
+                .map(|result| {
+                    let album_path = result.get_album_path().to_string();
+                    SearchEntry {
+                        photo: get_photo_entry(result.photo, &album_path, &urls),
+                        album_path,
+                    }
+                })
+                .collect();
+
+            HttpResponse::Ok()
+                .header(header::CACHE_CONTROL, "private, no-cache")
+                .json(SearchResponse {
+                    photos,
+                    total: search.total,
+                    sort: search.sort.as_param(),
+                    page: search.pagination.page,
+                    page_count: search.pagination.page_count,
+                    filters: search.filters,
+                })
+        })
+        .from_err()
+        .responder()
+}
+
+pub fn get_jobs_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = ApiError>>
+{
+    require_admin(&req)
+        .and_then(move |_| {
+            state.db.send(GetJobs)
+                .from_err::<GalleryError>()
+                .flatten()
+        })
+        .map(|jobs| HttpResponse::Ok().json(JobsResponse { jobs }))
+        .from_err()
+        .responder()
+}
+
+pub fn get_job_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = ApiError>>
+{
+    let job_id: String = future_try!(req.match_info().query("job_id").map_err(|e| ApiError(GalleryInternalError(e))));
+
+    require_admin(&req)
+        .and_then(move |_| {
+            let job_future = state.db.send(GetJob { job_id: job_id.clone() })
+                .from_err::<GalleryError>()
+                .flatten();
+            let errors_future = state.db.send(GetJobErrors { job_id })
+                .from_err::<GalleryError>()
+                .flatten();
+            job_future.join(errors_future)
+        })
+        .map(|(job, errors)| HttpResponse::Ok().json(JobResponse { job, errors }))
+        .from_err()
+        .responder()
+}
+
+pub fn post_jobs_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = ApiError>>
+{
+    let job_runners = state.jobs.clone();
+    let db_addr = state.db.clone();
+    let body_req = req.clone();
+
+    require_admin(&req)
+        .and_then(move |_| body_req.json::<JobRequest>().map_err(|e| GalleryError::ActixError(e.into())))
+        .and_then(move |job_request| {
+            if !JobRunners::KINDS.contains(&job_request.name.as_str()) {
+                return Err(GalleryError::InvalidForm("\"name\" field value is invalid."));
+            }
+            Ok(CreateJob { name: job_request.name })
+        })
+        .and_then(move |create_job| {
+            let name = create_job.name.clone();
+            state.db.send(create_job)
+                .from_err::<GalleryError>()
+                .flatten()
+                .map(|job_id| (name, job_id))
+        })
+        .and_then(move |(name, job_id)| {
+            job_runners.start(&name, job_id.clone());
+            db_addr.send(GetJob { job_id })
+                .from_err::<GalleryError>()
+                .flatten()
+        })
+        .map(|job| {
+            HttpResponse::Created()
+                .header(header::LOCATION, format!("/api/v1/jobs/{}", job.id))
+                .json(JobResponse { job, errors: Vec::new() })
+        })
+        .from_err()
+        .responder()
+}
+
+pub fn not_found_route(_req: &HttpRequest<AppState>) -> HttpResponse {
+    ApiError(GalleryError::NotFound).error_response()
+}
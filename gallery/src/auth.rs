@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::{HttpRequest, HttpResponse, Result, ResponseError};
+use actix_web::http::{header, Cookie};
+use actix_web::middleware::{Middleware, Started, Response};
+use cookie::SameSite;
+use futures::future::{self, Future};
+
+use crate::common::AppState;
+use crate::config::AuthConfig;
+use crate::error::GalleryError;
+use crate::utils::constant_time_eq;
+use crate::models::album::Viewer;
+use crate::models::user::{Session, Identity, GetIdentity};
+
+pub const SESSION_COOKIE: &str = "gallery_session";
+pub const CSRF_COOKIE: &str = "gallery_csrf";
+/// Prefix of the cookies holding the tokens of the unlocked albums, followed
+/// by the album id.
+pub const ALBUM_COOKIE_PREFIX: &str = "gallery_album_";
+/// Prefix of the cookies remembering the share links opened, followed by the
+/// share id.
+pub const SHARE_COOKIE_PREFIX: &str = "gallery_share_";
+pub const LOGIN_PATH: &str = "/admin/login";
+
+/// Token the forms must send back, matching the one stored in a cookie.
+#[derive(Clone)]
+struct CsrfToken {
+    value: String,
+    is_new: bool,
+    /// Whether a form of the response holds the token, the cookie only being
+    /// set then.
+    is_used: bool,
+}
+
+/// Provides the CSRF token of the forms, of the admin pages and of the
+/// locked albums.
+pub struct CsrfCookie;
+
+impl Middleware<AppState> for CsrfCookie {
+    fn start(&self, req: &HttpRequest<AppState>) -> Result<Started> {
+        let csrf_token = match req.cookie(CSRF_COOKIE) {
+            Some(ref cookie) if !cookie.value().is_empty() => {
+                CsrfToken { value: cookie.value().to_string(), is_new: false, is_used: false }
+            },
+            _ => CsrfToken { value: Session::generate_token(), is_new: true, is_used: false },
+        };
+        req.extensions_mut().insert(csrf_token);
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<AppState>, mut resp: HttpResponse) -> Result<Response> {
+        let csrf_token = req.extensions().get::<CsrfToken>().cloned();
+        if let Some(csrf_token) = csrf_token {
+            if csrf_token.is_new && csrf_token.is_used {
+                resp.add_cookie(&build_cookie(CSRF_COOKIE, csrf_token.value, "/", &req.state().config.auth))?;
+            }
+        }
+        Ok(Response::Done(resp))
+    }
+}
+
+/// Values shared by all the admin templates.
+pub struct AdminContext {
+    pub username: String,
+    pub csrf_token: String,
+    pub can_logout: bool,
+}
+
+impl AdminContext {
+    pub fn from_request(req: &HttpRequest<AppState>) -> AdminContext {
+        let identity = req.extensions().get::<Identity>().cloned();
+        AdminContext {
+            username: identity.as_ref().map(|identity| identity.username.clone()).unwrap_or_default(),
+            csrf_token: get_csrf_token(req),
+            can_logout: identity.map(|identity| identity.session_token.is_some()).unwrap_or(false),
+        }
+    }
+}
+
+/// Restricts the admin pages to the administrators.
+pub struct AdminAuth;
+
+impl Middleware<AppState> for AdminAuth {
+    fn start(&self, req: &HttpRequest<AppState>) -> Result<Started> {
+        if req.path() == LOGIN_PATH {
+            return Ok(Started::Done);
+        }
+
+        let req = req.clone();
+        let authenticated = get_identity(&req)
+            .map(move |identity| {
+                match identity {
+                    Some(ref identity) if identity.is_admin() => {
+                        req.extensions_mut().insert(identity.clone());
+                        None
+                    },
+                    Some(_) => Some(GalleryError::AdminRequired.error_response()),
+                    None => Some(redirect_to_login()),
+                }
+            })
+            .from_err();
+        Ok(Started::Future(Box::new(authenticated)))
+    }
+}
+
+/// Finds the user authenticated by the reverse proxy, or by the session
+/// cookie.
+pub fn get_identity(req: &HttpRequest<AppState>) -> Box<Future<Item = Option<Identity>, Error = GalleryError>> {
+    let (session_token, proxy_username) = match get_proxy_user(req, &req.state().config.auth) {
+        Some(username) => (None, Some(username)),
+        None => match req.cookie(SESSION_COOKIE) {
+            Some(cookie) => (Some(cookie.value().to_string()), None),
+            None => return Box::new(future::ok(None)),
+        },
+    };
+
+    Box::new(req.state().db.send(GetIdentity { session_token, proxy_username })
+        .from_err::<GalleryError>()
+        .flatten())
+}
+
+/// Gathers what grants access to the albums to the person making the request.
+pub fn get_viewer(req: &HttpRequest<AppState>) -> impl Future<Item = Viewer, Error = GalleryError> {
+    let get_cookie_values = |prefix: &str| -> Vec<String> {
+        req.cookies()
+            .map(|cookies| {
+                cookies.iter()
+                    .filter(|cookie| cookie.name().starts_with(prefix))
+                    .map(|cookie| cookie.value().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let unlock_tokens = get_cookie_values(ALBUM_COOKIE_PREFIX);
+    let mut share_tokens = get_cookie_values(SHARE_COOKIE_PREFIX);
+    if let Some(share_token) = get_share_query_token(req) {
+        share_tokens.push(share_token);
+    }
+
+    get_identity(req)
+        .map(|identity| Viewer { identity, unlock_tokens, share_tokens })
+}
+
+/// Token of the share link being followed, given in the query.
+pub fn get_share_query_token(req: &HttpRequest<AppState>) -> Option<String> {
+    req.query().get("share").cloned().filter(|token| !token.is_empty())
+}
+
+/// Name of the user given by the reverse proxy, only trusted when the
+/// request comes from it.
+fn get_proxy_user(req: &HttpRequest<AppState>, auth_config: &AuthConfig) -> Option<String> {
+    let header_name = auth_config.trusted_header.as_ref()?;
+    let peer_addr = req.peer_addr()?;
+    if !auth_config.trusted_proxies.contains(&peer_addr.ip()) {
+        return None;
+    }
+
+    let username = req.headers().get(header_name.as_str())?.to_str().ok()?.trim();
+    if username.is_empty() {
+        None
+    } else {
+        Some(username.to_string())
+    }
+}
+
+/// Address of the client, given by the X-Forwarded-For header when the
+/// request comes from a trusted proxy.
+pub fn get_client_ip(req: &HttpRequest<AppState>, auth_config: &AuthConfig) -> Option<IpAddr> {
+    let peer_ip = req.peer_addr()?.ip();
+    if !auth_config.trusted_proxies.contains(&peer_ip) {
+        return Some(peer_ip);
+    }
+
+    // The proxy appends the address it received the request from.
+    let forwarded_ip = req.headers().get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|value| value.trim().parse().ok());
+    Some(forwarded_ip.unwrap_or(peer_ip))
+}
+
+/// Failed logins counted by client address and by username, so that
+/// passwords can't be guessed by trying them one after the other.
+#[derive(Clone, Default)]
+pub struct LoginThrottle {
+    failures: Arc<Mutex<HashMap<String, Vec<Instant>>>>,
+}
+
+impl LoginThrottle {
+    fn get_keys(client_ip: Option<IpAddr>, username: &str) -> Vec<String> {
+        let mut keys = vec![format!("user:{}", username)];
+        keys.extend(client_ip.map(|ip| format!("ip:{}", ip)));
+        keys
+    }
+
+    /// Whether too many logins failed recently from the address or for the
+    /// username.
+    pub fn is_blocked(&self, client_ip: Option<IpAddr>, username: &str, auth_config: &AuthConfig) -> bool {
+        let lockout = Duration::from_secs(auth_config.login_lockout_minutes * 60);
+        let failures = self.failures.lock().unwrap();
+        Self::get_keys(client_ip, username).iter().any(|key| {
+            let recent_failures = failures.get(key)
+                .map(|times| times.iter().filter(|time| time.elapsed() < lockout).count())
+                .unwrap_or(0);
+            recent_failures >= auth_config.login_attempts
+        })
+    }
+
+    pub fn record_failure(&self, client_ip: Option<IpAddr>, username: &str, auth_config: &AuthConfig) {
+        let lockout = Duration::from_secs(auth_config.login_lockout_minutes * 60);
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, times| {
+            times.retain(|time| time.elapsed() < lockout);
+            !times.is_empty()
+        });
+        let now = Instant::now();
+        for key in Self::get_keys(client_ip, username) {
+            failures.entry(key).or_default().push(now);
+        }
+    }
+
+    /// Forgets the failed logins of the user once they logged in.
+    pub fn reset(&self, username: &str) {
+        self.failures.lock().unwrap().remove(&format!("user:{}", username));
+    }
+}
+
+fn redirect_to_login() -> HttpResponse {
+    HttpResponse::SeeOther()
+        .header(header::LOCATION, LOGIN_PATH)
+        .finish()
+}
+
+/// The cookies are sent when following links from other sites, the forms
+/// being protected by the CSRF tokens.
+pub fn build_cookie<N: Into<String>>(name: N, value: String, path: &'static str, auth_config: &AuthConfig) -> Cookie<'static> {
+    Cookie::build(name.into(), value)
+        .path(path)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(auth_config.secure_cookies)
+        .finish()
+}
+
+/// Token to put in a form, which sets the cookie holding it if missing.
+pub fn get_csrf_token(req: &HttpRequest<AppState>) -> String {
+    req.extensions_mut().get_mut::<CsrfToken>()
+        .map(|csrf_token| {
+            csrf_token.is_used = true;
+            csrf_token.value.clone()
+        })
+        .unwrap_or_default()
+}
+
+/// Checks the token submitted with a form against the one of the cookie,
+/// which a cross-site request can't read.
+pub fn check_csrf(req: &HttpRequest<AppState>, submitted_token: &str) -> Result<(), GalleryError> {
+    match req.extensions().get::<CsrfToken>() {
+        Some(csrf_token) if !csrf_token.is_new && constant_time_eq(csrf_token.value.as_bytes(), submitted_token.as_bytes()) => Ok(()),
+        _ => Err(GalleryError::Forbidden),
+    }
+}
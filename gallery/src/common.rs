@@ -1,12 +1,88 @@
+use std::collections::HashSet;
+
 use actix_web::actix::Addr;
+use futures::future::{Future, join_all};
 
+use crate::auth::LoginThrottle;
 use crate::config::Config;
+use crate::error::GalleryError;
 use crate::models::db::DbExecutor;
-use crate::indexer::walker_actor::WalkerActor;
+use crate::models::user::PasswordHasher;
+use crate::models::job::{CreateJob, InterruptRunningJobs, self};
+use crate::indexer::walker_actor::{WalkerActor, StartWalking};
+use crate::indexer::renderer_actor::RendererActor;
+use crate::indexer::maintenance_actor::{
+    MaintenanceActor, StartRebuildThumbnails, StartPurgeCache, StartVerifyIntegrity
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
     pub db: Addr<DbExecutor>,
+    pub hasher: Addr<PasswordHasher>,
+    pub login_throttle: LoginThrottle,
+    pub jobs: JobRunners,
+    /// Generates the missing thumbnails, when they are made on demand.
+    pub renderer: Option<Addr<RendererActor>>,
+}
+
+/// Dispatches the jobs to the actors running them.
+#[derive(Clone)]
+pub struct JobRunners {
     pub walker: Addr<WalkerActor>,
+    pub maintenance: Addr<MaintenanceActor>,
+}
+
+impl JobRunners {
+    /// Kinds of jobs which can be started on demand.
+    pub const KINDS: &'static [&'static str] = &[
+        job::JOB_INDEX_GALLERY,
+        job::JOB_REBUILD_THUMBNAILS,
+        job::JOB_PURGE_CACHE,
+        job::JOB_VERIFY_INTEGRITY,
+    ];
+
+    pub fn start(&self, name: &str, job_id: String) {
+        match name {
+            job::JOB_INDEX_GALLERY => self.walker.do_send(StartWalking { job_id }),
+            job::JOB_REBUILD_THUMBNAILS => self.maintenance.do_send(StartRebuildThumbnails { job_id }),
+            job::JOB_PURGE_CACHE => self.maintenance.do_send(StartPurgeCache { job_id }),
+            job::JOB_VERIFY_INTEGRITY => self.maintenance.do_send(StartVerifyIntegrity { job_id }),
+            _ => error!("Job {} has an unknown kind {:?}", job_id, name),
+        }
+    }
+
+    /// Marks the jobs left running by a previous instance of the server as
+    /// interrupted, and starts them again when `resume` is set.
+    pub fn recover(&self, db: Addr<DbExecutor>, resume: bool) -> impl Future<Item = (), Error = GalleryError> {
+        let runners = self.clone();
+
+        db.send(InterruptRunningJobs)
+            .from_err::<GalleryError>()
+            .flatten()
+            .and_then(move |interrupted_jobs| {
+                let mut resumed_kinds = HashSet::new();
+                let mut resumed_jobs = Vec::new();
+
+                for interrupted_job in interrupted_jobs {
+                    warn!("Job {} ({}) was interrupted.", interrupted_job.id, interrupted_job.name);
+
+                    if resume
+                        && Self::KINDS.contains(&interrupted_job.name.as_str())
+                        && resumed_kinds.insert(interrupted_job.name.clone())
+                    {
+                        let runners = runners.clone();
+                        let name = interrupted_job.name;
+                        resumed_jobs.push(db.send(CreateJob { name: name.clone() })
+                            .from_err::<GalleryError>()
+                            .flatten()
+                            .map(move |job_id| {
+                                info!("Resuming it as job {}.", job_id);
+                                runners.start(&name, job_id);
+                            }));
+                    }
+                }
+                join_all(resumed_jobs).map(|_| ())
+            })
+    }
 }
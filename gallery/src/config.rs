@@ -1,7 +1,8 @@
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
-use std::collections::{HashSet, HashMap};
+use std::collections::HashMap;
+use std::net::IpAddr;
 
 use toml;
 use serde::Deserialize;
@@ -11,6 +12,33 @@ pub struct ThumbnailConfig {
     pub size: u32,
     pub square: bool,
     pub extension: String,
+    /// Encodings generated along the main one, served to the browsers
+    /// accepting them.
+    #[serde(default)]
+    pub formats: Vec<ThumbnailFormat>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    Webp,
+    Avif,
+}
+
+impl ThumbnailFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Webp => "webp",
+            ThumbnailFormat::Avif => "avif",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Webp => "image/webp",
+            ThumbnailFormat::Avif => "image/avif",
+        }
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -18,6 +46,151 @@ pub struct DbConfig {
     pub url: String,
 }
 
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct WatcherConfig {
+    pub enabled: bool,
+    /// Delay in seconds during which events on a same file are merged,
+    /// so that bulk copies are only indexed once they are complete.
+    pub debounce_delay: u64,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        WatcherConfig {
+            enabled: false,
+            debounce_delay: 2,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DecodersConfig {
+    /// Program converting HEIF images to PNG, None to skip these files.
+    pub heif: Option<String>,
+    /// Program converting WebP images to PNG, None to skip these files.
+    pub webp: Option<String>,
+    /// Program converting RAW files without an embedded preview to PNG.
+    pub raw: Option<String>,
+    /// Programs extracting the frames and the metadata of videos, None to
+    /// skip these files.
+    pub ffmpeg: Option<String>,
+    pub ffprobe: Option<String>,
+}
+
+impl Default for DecodersConfig {
+    fn default() -> Self {
+        DecodersConfig {
+            heif: Some("heif-convert".to_string()),
+            webp: Some("dwebp".to_string()),
+            raw: None,
+            ffmpeg: Some("ffmpeg".to_string()),
+            ffprobe: Some("ffprobe".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RenderConfig {
+    /// Generate the thumbnails when they are first requested rather than
+    /// while indexing.
+    pub on_demand: bool,
+    /// Number of threads generating the requested thumbnails.
+    pub workers: usize,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            on_demand: false,
+            workers: 2,
+        }
+    }
+}
+
+/// Programs converting PNG images to the additional thumbnail formats, called
+/// with the source and destination paths.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct EncodersConfig {
+    pub webp: Option<String>,
+    pub avif: Option<String>,
+}
+
+impl EncodersConfig {
+    pub fn get(&self, format: ThumbnailFormat) -> Option<&String> {
+        match format {
+            ThumbnailFormat::Webp => self.webp.as_ref(),
+            ThumbnailFormat::Avif => self.avif.as_ref(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Days during which a login to the admin pages stays valid.
+    pub session_days: i64,
+    /// Send the cookies over HTTPS only.
+    pub secure_cookies: bool,
+    /// Header holding the name of the user authenticated by a reverse proxy,
+    /// None to only use the login page.
+    pub trusted_header: Option<String>,
+    /// Addresses of the reverse proxies allowed to set this header, and the
+    /// X-Forwarded-For one.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Failed logins from an address, or for a username, after which the
+    /// next attempts are refused.
+    pub login_attempts: usize,
+    /// Minutes during which the failed logins are counted.
+    pub login_lockout_minutes: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            session_days: 14,
+            secure_cookies: false,
+            trusted_header: None,
+            trusted_proxies: vec![IpAddr::from([127, 0, 0, 1]), IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1])],
+            login_attempts: 10,
+            login_lockout_minutes: 15,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AlbumsConfig {
+    /// Number of photos displayed on each page of the albums.
+    pub photos_per_page: i64,
+}
+
+impl Default for AlbumsConfig {
+    fn default() -> Self {
+        AlbumsConfig {
+            photos_per_page: 200,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ScheduledJobConfig {
+    pub name: String,
+    /// Cron expression with a leading seconds field, in local time.
+    pub schedule: String,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// Start again the jobs interrupted by a shutdown of the server.
+    pub resume_interrupted: bool,
+    pub scheduled: Vec<ScheduledJobConfig>,
+}
+
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub gallery_name: String,
@@ -25,11 +198,30 @@ pub struct Config {
     pub storage_path: String,
     pub cache_path: String,
 
-    pub allowed_extensions: HashSet<String>,
-
     pub thumbnails: HashMap<String, ThumbnailConfig>,
 
     pub db: DbConfig,
+
+    #[serde(default)]
+    pub albums: AlbumsConfig,
+
+    #[serde(default)]
+    pub decoders: DecodersConfig,
+
+    #[serde(default)]
+    pub encoders: EncodersConfig,
+
+    #[serde(default)]
+    pub render: RenderConfig,
+
+    #[serde(default)]
+    pub auth: AuthConfig,
+
+    #[serde(default)]
+    pub watcher: WatcherConfig,
+
+    #[serde(default)]
+    pub jobs: JobsConfig,
 }
 
 impl Config {
@@ -41,6 +233,24 @@ impl Config {
         config_file.read_to_string(&mut content).expect("Something went wrong reading the configuration.");
 
         let config: Config = toml::from_str(content.as_str()).expect("Could not parse configuration.");
+
+        // Thumbnails missing a format would be generated again on each request.
+        for (name, thumbnail_config) in &config.thumbnails {
+            for &format in &thumbnail_config.formats {
+                if config.encoders.get(format).is_none() {
+                    panic!("No encoder configured for the {} format of the {} thumbnails.", format.extension(), name);
+                }
+            }
+        }
+
+        // The names end up in the route of the thumbnails, next to the one of
+        // the full photos.
+        for name in config.thumbnails.keys() {
+            let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if name.is_empty() || name == "full" || !valid {
+                panic!("Invalid thumbnail size name \"{}\".", name);
+            }
+        }
         config
     }
 }
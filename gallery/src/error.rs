@@ -6,8 +6,11 @@ use image::ImageError;
 use diesel::result::Error as DieselError;
 use actix_web::Error as ActixError;
 use actix_web::{ResponseError, HttpResponse};
+use actix_web::http::StatusCode;
 use actix_web::actix::MailboxError;
 
+use crate::models::album::AccessGrant;
+
 #[derive(Fail, Debug)]
 pub enum GalleryError {
     #[fail(display="Resource not found")]
@@ -16,6 +19,14 @@ pub enum GalleryError {
     ImageError(ImageError),
     #[fail(display="Error processing a file with an invalid file name")]
     InvalidFileName,
+    #[fail(display="Error processing a file in an unsupported format")]
+    UnsupportedFormat,
+    #[fail(display="Error decoding image, {}", _0)]
+    DecoderError(String),
+    #[fail(display="Error encoding image, {}", _0)]
+    EncoderError(String),
+    #[fail(display="Error rendering thumbnail, {}", _0)]
+    RenderError(String),
     #[fail(display="{}", _0)]
     InternalError(Box<Fail>),
     #[fail(display="Database Error: {}", _0)]
@@ -25,11 +36,67 @@ pub enum GalleryError {
         missing_segments: u8,
         last_album: String,
         current_breadcrumb: Vec<(String, String)>,
+        grant: AccessGrant,
     },
     #[fail(display = "Actix Error: {}", _0)]
     ActixError(ActixError),
     #[fail(display = "Invalid form submitted, {}", _0)]
     InvalidForm(&'static str),
+    #[fail(display = "A job of the same kind is already running")]
+    JobAlreadyRunning,
+    #[fail(display = "Job cancelled")]
+    JobCancelled,
+    #[fail(display = "Invalid username or password")]
+    InvalidCredentials,
+    #[fail(display = "Too many failed logins, try again later")]
+    TooManyAttempts,
+    #[fail(display = "Invalid or missing CSRF token")]
+    Forbidden,
+    #[fail(display = "Authentication required")]
+    Unauthenticated,
+    #[fail(display = "Only administrators can access this page")]
+    AdminRequired,
+    #[fail(display = "Album {} is protected by a password", name)]
+    AlbumLocked {
+        album_id: String,
+        name: String,
+    },
+    #[fail(display = "Integrity check failed, {}", _0)]
+    IntegrityError(&'static str),
+}
+
+impl GalleryError {
+    pub fn status_code(&self) -> StatusCode {
+        match *self {
+            GalleryError::NotFound | GalleryError::AlbumNotFound { .. } => StatusCode::NOT_FOUND,
+            GalleryError::InvalidForm(_) => StatusCode::BAD_REQUEST,
+            GalleryError::JobAlreadyRunning => StatusCode::CONFLICT,
+            GalleryError::InvalidCredentials | GalleryError::Unauthenticated | GalleryError::AlbumLocked { .. } => {
+                StatusCode::UNAUTHORIZED
+            },
+            GalleryError::Forbidden | GalleryError::AdminRequired => StatusCode::FORBIDDEN,
+            GalleryError::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
+            GalleryError::ActixError(ref e) => e.as_response_error().error_response().status(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Kind of the error, given to the clients of the API.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            GalleryError::NotFound | GalleryError::AlbumNotFound { .. } => "not_found",
+            GalleryError::InvalidForm(_) => "invalid_request",
+            GalleryError::JobAlreadyRunning => "job_already_running",
+            GalleryError::InvalidCredentials => "invalid_credentials",
+            GalleryError::TooManyAttempts => "too_many_attempts",
+            GalleryError::Unauthenticated => "unauthenticated",
+            GalleryError::Forbidden => "forbidden",
+            GalleryError::AdminRequired => "admin_required",
+            GalleryError::AlbumLocked { .. } => "album_locked",
+            GalleryError::ActixError(_) if self.status_code().is_client_error() => "invalid_request",
+            _ => "internal_error",
+        }
+    }
 }
 
 impl ResponseError for GalleryError {
@@ -39,6 +106,13 @@ impl ResponseError for GalleryError {
                 HttpResponse::NotFound().content_type("text/html").finish()
             },
             GalleryError::InvalidForm(msg) => HttpResponse::BadRequest().body(msg),
+            GalleryError::JobAlreadyRunning
+            | GalleryError::InvalidCredentials
+            | GalleryError::TooManyAttempts
+            | GalleryError::Unauthenticated
+            | GalleryError::Forbidden
+            | GalleryError::AdminRequired
+            | GalleryError::AlbumLocked { .. } => HttpResponse::build(self.status_code()).body(self.to_string()),
             GalleryError::ActixError(ref e) => e.as_response_error().error_response(),
             _ => HttpResponse::InternalServerError().content_type("text/html").finish()
         }
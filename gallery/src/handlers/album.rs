@@ -1,13 +1,103 @@
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
 use actix_web::actix::Handler;
 use uuid;
 use diesel;
 use diesel::prelude::*;
 use diesel::result::Error as DieselError;
 
-use crate::models::db::{DbExecutor};
-use crate::models::album::{Album, AlbumResult, CreateAlbum, GetAlbum, GetAlbumId, GetRootAlbumId};
+use crate::models::db::{DbExecutor, MAX_BOUND_PARAMETERS};
+use crate::models::album::{
+    Album, AlbumResult, AccessGrant, Viewer, CreateAlbum, GetAlbum, GetAlbumId, GetRootAlbumId, GetAlbumPath, SetAlbumAccess,
+    SetAlbumSort, GetLockedAlbum, DeleteAlbum, DeleteStaleAlbums, ACCESS_PUBLIC, ACCESS_RESTRICTED
+};
+use crate::models::user::User;
+use crate::handlers::share::get_active_shares;
 use crate::error::GalleryError;
 
+/// Finds the album at the path relative to the storage.
+pub fn find_album(conn: &SqliteConnection, path: &Path) -> Result<Album, GalleryError> {
+    use crate::models::schema::albums;
+
+    let mut album = albums::table
+        .filter(albums::parent_album_id.is_null())
+        .first::<Album>(conn)?;
+    for segment in path.iter() {
+        album = Album::belonging_to(&album)
+            .filter(albums::name.eq(segment.to_str().ok_or(GalleryError::InvalidFileName)?))
+            .first::<Album>(conn)?;
+    }
+    Ok(album)
+}
+
+/// Path of the album relative to the storage.
+pub fn get_album_path(conn: &SqliteConnection, album_id: &str) -> Result<PathBuf, GalleryError> {
+    use crate::models::schema::albums;
+
+    let mut current_album = albums::table.find(album_id).first::<Album>(conn)?;
+    let mut segments = Vec::new();
+
+    // The root album name is the gallery name, not a directory.
+    while let Some(parent_id) = current_album.parent_album_id.take() {
+        segments.push(current_album.name);
+        current_album = albums::table.find(&parent_id).first::<Album>(conn)?;
+    }
+
+    Ok(segments.iter().rev().collect())
+}
+
+/// Checks the access to the album on its own, loading the principals it
+/// is restricted to.
+pub fn check_album_access(conn: &SqliteConnection, album: &Album, viewer: &Viewer) -> Result<(), GalleryError> {
+    use crate::models::schema::album_grants;
+
+    let grants = if album.access == ACCESS_RESTRICTED && !viewer.is_admin() {
+        album_grants::table
+            .filter(album_grants::album_id.eq(&album.id))
+            .select(album_grants::principal)
+            .load::<String>(conn)?
+    } else {
+        Vec::new()
+    };
+    album.check_access(viewer, &grants)
+}
+
+/// URLs of the albums the viewer can browse without a share link, by their
+/// id. The restrictions of an album also apply to its sub-albums.
+pub fn get_browsable_albums(conn: &SqliteConnection, viewer: &Viewer) -> Result<HashMap<String, String>, GalleryError> {
+    use crate::models::schema::{albums, album_grants};
+
+    let all_albums = albums::table.load::<Album>(conn)?;
+    let mut grants: HashMap<String, Vec<String>> = HashMap::new();
+    if !viewer.is_admin() {
+        for (album_id, principal) in album_grants::table.load::<(String, String)>(conn)? {
+            grants.entry(album_id).or_default().push(principal);
+        }
+    }
+    let mut children: HashMap<Option<&str>, Vec<&Album>> = HashMap::new();
+    for album in &all_albums {
+        children.entry(album.parent_album_id.as_deref()).or_default().push(album);
+    }
+
+    let mut urls = HashMap::new();
+    let mut stack: Vec<(&Album, String)> = children.get(&None)
+        .map(|roots| roots.iter().map(|album| (*album, String::new())).collect())
+        .unwrap_or_default();
+    while let Some((album, url)) = stack.pop() {
+        let album_grants = grants.get(&album.id).map(Vec::as_slice).unwrap_or(&[]);
+        if album.check_access(viewer, album_grants).is_err() {
+            continue;
+        }
+        for child in children.get(&Some(album.id.as_str())).map(Vec::as_slice).unwrap_or(&[]) {
+            stack.push((child, format!("{}/{}", url, child.name)));
+        }
+        urls.insert(album.id.clone(), url);
+    }
+
+    Ok(urls)
+}
+
 impl Handler<CreateAlbum> for DbExecutor {
     type Result = Result<String, GalleryError>;
 
@@ -20,6 +110,9 @@ impl Handler<CreateAlbum> for DbExecutor {
             id: uuid,
             name: msg.name,
             parent_album_id: msg.parent_album_id,
+            access: ACCESS_PUBLIC.to_string(),
+            password_hash: None,
+            sort: None,
         };
 
         diesel::insert_into(albums::table)
@@ -40,26 +133,23 @@ impl Handler<GetAlbum> for DbExecutor {
 
         let conn = self.conn.get().unwrap();
         let albums_names: Vec<_> = msg.path.iter().map(|e| e.to_str().unwrap()).collect();
-        let mut current_album = albums
+        let root_album = albums
             .filter(parent_album_id.is_null())
             .first::<Album>(&conn)?;
 
-        let mut breadcrumb: Vec<(String, String)> = vec![(String::from("/"), current_album.name.clone())];
+        let mut breadcrumb: Vec<(String, String)> = vec![(String::from("/"), root_album.name.clone())];
         let mut current_path = String::from("");
         let mut missing_segments = albums_names.len() as u8;
+        let mut album_chain = vec![root_album];
 
-        for album_name in albums_names {
-            let result = Album::belonging_to(&current_album)
+        for album_name in &albums_names {
+            let result = Album::belonging_to(&album_chain[album_chain.len() - 1])
                 .filter(name.eq(album_name))
                 .first::<Album>(&conn);
 
-            current_album = match result {
+            let current_album = match result {
                 Ok(album) => album,
-                Err(DieselError::NotFound) => return Err(GalleryError::AlbumNotFound {
-                    missing_segments,
-                    last_album: current_album.id,
-                    current_breadcrumb: breadcrumb,
-                }),
+                Err(DieselError::NotFound) => break,
                 Err(e) => return Err(GalleryError::DbError(e))
             };
 
@@ -68,6 +158,27 @@ impl Handler<GetAlbum> for DbExecutor {
 
             breadcrumb.push((current_path.clone(), current_album.name.clone()));
             missing_segments -= 1;
+            album_chain.push(current_album);
+        }
+
+        let photo_name = match missing_segments {
+            0 => msg.photo_name,
+            1 => albums_names.last().map(|photo_name| photo_name.to_string()),
+            _ => None,
+        };
+        // Access is checked before telling whether the rest of the path
+        // exists.
+        let grant = get_access_grant(&conn, &album_chain, photo_name, &msg.viewer)?;
+        let is_restricted = album_chain.iter().any(|album| album.access != ACCESS_PUBLIC);
+        let current_album = album_chain.pop().unwrap();
+
+        if missing_segments > 0 {
+            return Err(GalleryError::AlbumNotFound {
+                missing_segments,
+                last_album: current_album.id,
+                current_breadcrumb: breadcrumb,
+                grant,
+            });
         }
 
         breadcrumb.pop();
@@ -75,10 +186,65 @@ impl Handler<GetAlbum> for DbExecutor {
         Ok(AlbumResult {
             album: current_album,
             breadcrumb: breadcrumb,
+            is_restricted,
+            grant,
         })
     }
 }
 
+/// Checks the access to the albums from the root, the restrictions of the
+/// parents applying to the sub-albums, then falls back on the share links
+/// followed by the viewer.
+fn get_access_grant(conn: &SqliteConnection, album_chain: &[Album], photo_name: Option<String>, viewer: &Viewer)
+    -> Result<AccessGrant, GalleryError>
+{
+    use crate::models::schema::photos;
+
+    let access_error = match album_chain.iter().map(|album| check_album_access(conn, album, viewer)).find_map(Result::err) {
+        None => return Ok(AccessGrant::Direct),
+        Some(e @ GalleryError::NotFound) | Some(e @ GalleryError::AlbumLocked { .. }) => e,
+        Some(e) => return Err(e),
+    };
+    if viewer.share_tokens.is_empty() {
+        return Err(access_error);
+    }
+
+    let shares = get_active_shares(conn, &viewer.share_tokens)?;
+    let album_share = shares.iter()
+        .filter(|share| share.photo_id.is_none() && album_chain.iter().any(|album| album.id == share.album_id))
+        .map(|share| share.allow_download)
+        .max();
+    if let Some(allow_download) = album_share {
+        return Ok(AccessGrant::SharedAlbum { allow_download });
+    }
+
+    if let Some(photo_name) = photo_name {
+        let current_album = &album_chain[album_chain.len() - 1];
+        let requested_photo = photos::table
+            .filter(photos::album_id.eq(&current_album.id))
+            .filter(photos::name.eq(&photo_name))
+            .select((photos::id, photos::primary_photo_id))
+            .first::<(String, Option<String>)>(conn)
+            .optional()?;
+        // The variants stacked under the photo shared are listed along it,
+        // and shared with it.
+        if let Some((photo_id, primary_photo_id)) = requested_photo {
+            let photo_share = shares.iter()
+                .filter(|share| share.album_id == current_album.id)
+                .filter(|share| share.photo_id.as_ref()
+                    .map(|shared_id| shared_id == &photo_id || Some(shared_id) == primary_photo_id.as_ref())
+                    .unwrap_or(false))
+                .map(|share| share.allow_download)
+                .max();
+            if let Some(allow_download) = photo_share {
+                return Ok(AccessGrant::SharedPhoto { allow_download });
+            }
+        }
+    }
+
+    Err(access_error)
+}
+
 impl Handler<GetAlbumId> for DbExecutor {
     type Result = Result<Option<String>, GalleryError>;
 
@@ -111,3 +277,139 @@ impl Handler<GetRootAlbumId> for DbExecutor {
         Ok(album_id)
     }
 }
+
+impl Handler<GetAlbumPath> for DbExecutor {
+    type Result = Result<PathBuf, GalleryError>;
+
+    fn handle(&mut self, msg: GetAlbumPath, _ctx: &mut Self::Context) -> Self::Result {
+        get_album_path(&self.conn.get().unwrap(), &msg.album_id)
+    }
+}
+
+impl Handler<SetAlbumAccess> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: SetAlbumAccess, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::{albums, album_grants};
+
+        let conn = self.conn.get().unwrap();
+        let album = find_album(&conn, &msg.path)?;
+
+        let password_hash = match msg.password {
+            Some(ref password) => Some(User::hash_password(password)?),
+            None => None,
+        };
+
+        conn.transaction::<_, DieselError, _>(|| {
+            diesel::update(albums::table.find(&album.id))
+                .set((albums::access.eq(&msg.access), albums::password_hash.eq(&password_hash)))
+                .execute(&conn)?;
+            diesel::delete(album_grants::table.filter(album_grants::album_id.eq(&album.id)))
+                .execute(&conn)?;
+            for principal in &msg.principals {
+                diesel::insert_into(album_grants::table)
+                    .values((album_grants::album_id.eq(&album.id), album_grants::principal.eq(principal)))
+                    .execute(&conn)?;
+            }
+            Ok(())
+        })?;
+
+        info!("Access to album {:?} set to {}.", msg.path, msg.access);
+        Ok(())
+    }
+}
+
+impl Handler<SetAlbumSort> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: SetAlbumSort, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::albums;
+
+        let conn = self.conn.get().unwrap();
+        let album = find_album(&conn, &msg.path)?;
+
+        diesel::update(albums::table.find(&album.id))
+            .set(albums::sort.eq(msg.sort.as_param()))
+            .execute(&conn)?;
+
+        info!("Order of album {:?} set to {}.", msg.path, msg.sort.as_param());
+        Ok(())
+    }
+}
+
+impl Handler<GetLockedAlbum> for DbExecutor {
+    type Result = Result<Album, GalleryError>;
+
+    fn handle(&mut self, msg: GetLockedAlbum, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::albums::dsl::*;
+
+        Ok(albums.find(&msg.album_id).first::<Album>(&self.conn.get().unwrap())?)
+    }
+}
+
+impl Handler<DeleteAlbum> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: DeleteAlbum, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::{albums, album_grants, photos, shares};
+
+        let conn = self.conn.get().unwrap();
+        let mut album_ids = vec![msg.album_id.clone()];
+        let mut current_level = album_ids.clone();
+
+        while !current_level.is_empty() {
+            current_level = albums::table
+                .filter(albums::parent_album_id.eq_any(&current_level))
+                .select(albums::id)
+                .load::<String>(&conn)?;
+            album_ids.extend(current_level.iter().cloned());
+        }
+
+        conn.transaction::<_, DieselError, _>(|| {
+            for chunk in album_ids.chunks(MAX_BOUND_PARAMETERS) {
+                diesel::delete(photos::table.filter(photos::album_id.eq_any(chunk)))
+                    .execute(&conn)?;
+                diesel::delete(album_grants::table.filter(album_grants::album_id.eq_any(chunk)))
+                    .execute(&conn)?;
+                diesel::delete(shares::table.filter(shares::album_id.eq_any(chunk)))
+                    .execute(&conn)?;
+                diesel::delete(albums::table.filter(albums::id.eq_any(chunk)))
+                    .execute(&conn)?;
+            }
+            Ok(())
+        })?;
+
+        debug!("Removed album {} and its {} sub-albums from database.", msg.album_id, album_ids.len() - 1);
+
+        Ok(())
+    }
+}
+
+impl Handler<DeleteStaleAlbums> for DbExecutor {
+    type Result = Result<usize, GalleryError>;
+
+    fn handle(&mut self, msg: DeleteStaleAlbums, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::{albums, album_grants, shares};
+        let conn = self.conn.get().unwrap();
+
+        let stale_ids: Vec<String> = albums::table
+            .select(albums::id)
+            .load::<String>(&conn)?
+            .into_iter()
+            .filter(|album_id| !msg.seen_albums.contains(album_id))
+            .collect();
+
+        for chunk in stale_ids.chunks(MAX_BOUND_PARAMETERS) {
+            diesel::delete(album_grants::table.filter(album_grants::album_id.eq_any(chunk)))
+                .execute(&conn)?;
+            diesel::delete(shares::table.filter(shares::album_id.eq_any(chunk)))
+                .execute(&conn)?;
+            diesel::delete(albums::table.filter(albums::id.eq_any(chunk)))
+                .execute(&conn)?;
+        }
+
+        debug!("Removed {} stale albums from database.", stale_ids.len());
+
+        Ok(stale_ids.len())
+    }
+}
@@ -3,7 +3,9 @@ use diesel;
 use diesel::prelude::*;
 
 use crate::models::db::{DbExecutor};
+use crate::models::album::{Album, AccessGrant};
 use crate::models::album_thumbnail::{AlbumThumbnail, GetAlbumsThumbnail};
+use crate::handlers::album::check_album_access;
 use crate::error::GalleryError;
 
 impl Handler<GetAlbumsThumbnail> for DbExecutor {
@@ -12,10 +14,25 @@ impl Handler<GetAlbumsThumbnail> for DbExecutor {
     fn handle(&mut self, msg: GetAlbumsThumbnail, _ctx: &mut Self::Context) -> Self::Result {
         use crate::models::schema::albums::dsl::*;
 
-        let thumbnails = albums.filter(parent_album_id.eq(msg.parent_album_id))
-            .select((name,))
+        let conn = self.conn.get().unwrap();
+        let sub_albums = albums.filter(parent_album_id.eq(msg.parent_album_id))
             .order(name.asc())
-            .load::<AlbumThumbnail>(&self.conn.get().unwrap())?;
+            .load::<Album>(&conn)?;
+
+        let mut thumbnails = Vec::new();
+        for album in sub_albums {
+            let album_access = match msg.grant {
+                AccessGrant::SharedAlbum { .. } => Ok(()),
+                _ => check_album_access(&conn, &album, &msg.viewer),
+            };
+            // Albums protected by a password are listed, to be unlocked.
+            match album_access {
+                Ok(()) => thumbnails.push(AlbumThumbnail { name: album.name, is_locked: false }),
+                Err(GalleryError::AlbumLocked { .. }) => thumbnails.push(AlbumThumbnail { name: album.name, is_locked: true }),
+                Err(GalleryError::NotFound) => (),
+                Err(e) => return Err(e),
+            }
+        }
 
         Ok(thumbnails)
     }
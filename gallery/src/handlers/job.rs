@@ -2,9 +2,13 @@ use actix_web::actix::Handler;
 use uuid;
 use diesel;
 use diesel::prelude::*;
+use chrono::Utc;
 
 use crate::models::db::DbExecutor;
-use crate::models::job::{Job, CreateJob, ChangeState, GetJobs, self};
+use crate::models::job::{
+    Job, JobError, CreateJob, ChangeState, CancelJob, FailJob, InterruptRunningJobs,
+    UpdateProgress, LogError, GetJobs, GetJob, GetJobErrors, self
+};
 use crate::error::GalleryError;
 
 impl Handler<CreateJob> for DbExecutor {
@@ -13,17 +17,38 @@ impl Handler<CreateJob> for DbExecutor {
     fn handle(&mut self, msg: CreateJob, _ctx: &mut Self::Context) -> Self::Result {
         use crate::models::schema::jobs;
 
+        let conn = self.conn.get().unwrap();
+
+        let active_jobs: i64 = jobs::table
+            .filter(jobs::name.eq(&msg.name))
+            .filter(jobs::state.eq_any(job::ACTIVE_STATES))
+            .count()
+            .get_result(&conn)?;
+
+        if active_jobs > 0 {
+            return Err(GalleryError::JobAlreadyRunning);
+        }
+
         let uuid = uuid::Uuid::new_v4().to_string();
 
         let new_job = Job {
             id: uuid,
             name: msg.name,
             state: job::STATE_CREATED.to_string(),
+            started_at: None,
+            ended_at: None,
+            files_seen: 0,
+            files_indexed: 0,
+            files_skipped: 0,
+            files_failed: 0,
+            current_directory: None,
+            failure_reason: None,
+            files_processed: 0,
         };
 
         diesel::insert_into(jobs::table)
             .values(&new_job)
-            .execute(&self.conn.get().unwrap())?;
+            .execute(&conn)?;
 
         debug!("New job {} created.", new_job.id);
 
@@ -37,15 +62,140 @@ impl Handler<ChangeState> for DbExecutor {
     fn handle(&mut self, msg: ChangeState, _ctx: &mut Self::Context) -> Self::Result {
         use crate::models::schema::jobs::dsl::*;
 
+        let conn = self.conn.get().unwrap();
+        let now = Utc::now().naive_utc();
+
         diesel::update(jobs.find(&msg.job_id))
             .set(state.eq(&msg.new_state))
-            .execute(&self.conn.get().unwrap())?;
+            .execute(&conn)?;
+
+        if msg.new_state == job::STATE_RUNNING {
+            diesel::update(jobs.find(&msg.job_id))
+                .set(started_at.eq(now))
+                .execute(&conn)?;
+        } else if msg.new_state == job::STATE_FINISHED || msg.new_state == job::STATE_CANCELLED {
+            diesel::update(jobs.find(&msg.job_id))
+                .set((ended_at.eq(now), current_directory.eq(None::<String>)))
+                .execute(&conn)?;
+        }
 
         debug!("Changed state of job {:?} to {:?}.", msg.job_id, msg.new_state);
         Ok(())
     }
 }
 
+impl Handler<CancelJob> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: CancelJob, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::jobs::dsl::*;
+
+        let conn = self.conn.get().unwrap();
+        let current_job = jobs.find(&msg.job_id).first::<Job>(&conn)?;
+
+        if !current_job.is_cancellable() {
+            return Err(GalleryError::InvalidForm("This job can not be cancelled."));
+        }
+
+        // A job not started yet is cancelled right away, a running one is
+        // stopped by its actor the next time it checks its state.
+        if current_job.state == job::STATE_CREATED {
+            diesel::update(jobs.find(&msg.job_id))
+                .set((state.eq(job::STATE_CANCELLED), ended_at.eq(Utc::now().naive_utc())))
+                .execute(&conn)?;
+        } else {
+            diesel::update(jobs.find(&msg.job_id))
+                .set(state.eq(job::STATE_CANCELLING))
+                .execute(&conn)?;
+        }
+
+        debug!("Cancellation of job {:?} requested.", msg.job_id);
+        Ok(())
+    }
+}
+
+impl Handler<FailJob> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: FailJob, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::jobs::dsl::*;
+
+        diesel::update(jobs.find(&msg.job_id))
+            .set((
+                state.eq(job::STATE_FAILED),
+                failure_reason.eq(&msg.reason),
+                ended_at.eq(Utc::now().naive_utc()),
+                current_directory.eq(None::<String>),
+            ))
+            .execute(&self.conn.get().unwrap())?;
+
+        debug!("Job {:?} failed, {}", msg.job_id, msg.reason);
+        Ok(())
+    }
+}
+
+impl Handler<InterruptRunningJobs> for DbExecutor {
+    type Result = Result<Vec<Job>, GalleryError>;
+
+    fn handle(&mut self, _msg: InterruptRunningJobs, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::jobs::dsl::*;
+
+        let conn = self.conn.get().unwrap();
+
+        conn.transaction(|| {
+            let orphan_jobs = jobs
+                .filter(state.eq_any(job::ACTIVE_STATES))
+                .load::<Job>(&conn)?;
+
+            diesel::update(jobs.filter(state.eq_any(job::ACTIVE_STATES)))
+                .set((
+                    state.eq(job::STATE_INTERRUPTED),
+                    failure_reason.eq("The server stopped before the job could complete."),
+                    ended_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(&conn)?;
+
+            Ok(orphan_jobs)
+        })
+    }
+}
+
+impl Handler<UpdateProgress> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: UpdateProgress, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::jobs::dsl::*;
+
+        diesel::update(jobs.find(&msg.job_id))
+            .set(&msg.progress)
+            .execute(&self.conn.get().unwrap())?;
+
+        Ok(())
+    }
+}
+
+impl Handler<LogError> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: LogError, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::job_errors;
+
+        let new_error = JobError {
+            id: uuid::Uuid::new_v4().to_string(),
+            job_id: msg.job_id,
+            path: msg.path,
+            message: msg.message,
+            created_at: Utc::now().naive_utc(),
+        };
+
+        diesel::insert_into(job_errors::table)
+            .values(&new_error)
+            .execute(&self.conn.get().unwrap())?;
+
+        Ok(())
+    }
+}
+
 impl Handler<GetJobs> for DbExecutor {
     type Result = Result<Vec<Job>, GalleryError>;
 
@@ -55,3 +205,28 @@ impl Handler<GetJobs> for DbExecutor {
         Ok(jobs.load::<Job>(&self.conn.get().unwrap())?)
     }
 }
+
+impl Handler<GetJob> for DbExecutor {
+    type Result = Result<Job, GalleryError>;
+
+    fn handle(&mut self, msg: GetJob, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::jobs::dsl::*;
+
+        Ok(jobs.find(&msg.job_id).first::<Job>(&self.conn.get().unwrap())?)
+    }
+}
+
+impl Handler<GetJobErrors> for DbExecutor {
+    type Result = Result<Vec<JobError>, GalleryError>;
+
+    fn handle(&mut self, msg: GetJobErrors, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::job_errors::dsl::*;
+
+        let errors = job_errors
+            .filter(job_id.eq(&msg.job_id))
+            .order(created_at.asc())
+            .load::<JobError>(&self.conn.get().unwrap())?;
+
+        Ok(errors)
+    }
+}
@@ -3,3 +3,7 @@ pub mod photo;
 pub mod album_thumbnail;
 pub mod photo_thumbnail;
 pub mod job;
+pub mod user;
+pub mod share;
+pub mod search;
+pub mod timeline;
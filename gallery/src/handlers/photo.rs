@@ -1,10 +1,21 @@
+use std::collections::{HashMap, HashSet};
+
 use actix_web::actix::Handler;
 use uuid;
 use diesel;
 use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use chrono::NaiveDateTime;
 
-use crate::models::db::{DbExecutor};
-use crate::models::photo::{Photo, CreatePhoto, GetPhoto, GetPhotoId, GetAdjacentPhotos};
+use crate::models::db::{DbExecutor, MAX_BOUND_PARAMETERS};
+use crate::models::photo::{
+    Photo, CreatePhoto, GetPhoto, FindPhoto, GetPhotosByHash, GetAlbumPhotoNames,
+    GetAllPhotos, GetPhotoHashes, UpdatePhoto, DeletePhoto, DeleteStalePhotos, StackPhotos,
+    GetPhotoVariants, GetAdjacentPhotos
+};
+use crate::models::album::Album;
+use crate::models::photo_thumbnail::PhotoSort;
+use crate::handlers::photo_thumbnail::order_photos;
 use crate::error::GalleryError;
 
 impl Handler<CreatePhoto> for DbExecutor {
@@ -42,20 +53,203 @@ impl Handler<GetPhoto> for DbExecutor {
     }
 }
 
-impl Handler<GetPhotoId> for DbExecutor {
-    type Result = Result<Option<String>, GalleryError>;
+impl Handler<FindPhoto> for DbExecutor {
+    type Result = Result<Option<Photo>, GalleryError>;
 
-    fn handle(&mut self, msg: GetPhotoId, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: FindPhoto, _ctx: &mut Self::Context) -> Self::Result {
         use crate::models::schema::photos::dsl::*;
 
-        let photo_id = photos
+        let photo = photos
             .filter(album_id.eq(&msg.album_id))
             .filter(name.eq(&msg.name))
-            .select(id)
-            .load::<String>(&self.conn.get().unwrap())?
+            .load::<Photo>(&self.conn.get().unwrap())?
             .pop();
 
-        Ok(photo_id)
+        Ok(photo)
+    }
+}
+
+impl Handler<GetPhotosByHash> for DbExecutor {
+    type Result = Result<Vec<Photo>, GalleryError>;
+
+    fn handle(&mut self, msg: GetPhotosByHash, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+
+        Ok(photos.filter(hash.eq(&msg.hash)).load::<Photo>(&self.conn.get().unwrap())?)
+    }
+}
+
+impl Handler<GetAlbumPhotoNames> for DbExecutor {
+    type Result = Result<HashMap<String, String>, GalleryError>;
+
+    fn handle(&mut self, msg: GetAlbumPhotoNames, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+
+        let names = photos
+            .filter(album_id.eq(&msg.album_id))
+            .select((name, id))
+            .load::<(String, String)>(&self.conn.get().unwrap())?
+            .into_iter()
+            .collect();
+
+        Ok(names)
+    }
+}
+
+impl Handler<GetAllPhotos> for DbExecutor {
+    type Result = Result<Vec<Photo>, GalleryError>;
+
+    fn handle(&mut self, _msg: GetAllPhotos, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+
+        Ok(photos.order(album_id.asc()).load::<Photo>(&self.conn.get().unwrap())?)
+    }
+}
+
+impl Handler<GetPhotoHashes> for DbExecutor {
+    type Result = Result<HashSet<String>, GalleryError>;
+
+    fn handle(&mut self, _msg: GetPhotoHashes, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+
+        let hashes = photos
+            .select(hash)
+            .distinct()
+            .load::<String>(&self.conn.get().unwrap())?
+            .into_iter()
+            .collect();
+
+        Ok(hashes)
+    }
+}
+
+impl Handler<UpdatePhoto> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: UpdatePhoto, _ctx: &mut Self::Context) -> Self::Result {
+        diesel::update(&msg.photo)
+            .set(&msg.photo)
+            .execute(&self.conn.get().unwrap())?;
+
+        debug!("Updated photo in database {} -> {}", msg.photo.id, msg.photo.name);
+
+        Ok(())
+    }
+}
+
+impl Handler<DeletePhoto> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: DeletePhoto, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+        use crate::models::schema::shares;
+
+        let conn = self.conn.get().unwrap();
+
+        diesel::delete(photos.find(&msg.photo_id))
+            .execute(&conn)?;
+        diesel::delete(shares::table.filter(shares::photo_id.eq(&msg.photo_id)))
+            .execute(&conn)?;
+        // Its variants are now photos on their own.
+        diesel::update(photos.filter(primary_photo_id.eq(&msg.photo_id)))
+            .set(primary_photo_id.eq(None::<String>))
+            .execute(&conn)?;
+
+        debug!("Removed photo {} from database.", msg.photo_id);
+
+        Ok(())
+    }
+}
+
+impl Handler<DeleteStalePhotos> for DbExecutor {
+    type Result = Result<usize, GalleryError>;
+
+    fn handle(&mut self, msg: DeleteStalePhotos, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+        use crate::models::schema::shares;
+        let conn = self.conn.get().unwrap();
+
+        let stale_ids: Vec<String> = photos
+            .select(id)
+            .load::<String>(&conn)?
+            .into_iter()
+            .filter(|photo_id| !msg.seen_photos.contains(photo_id))
+            .collect();
+
+        for chunk in stale_ids.chunks(MAX_BOUND_PARAMETERS) {
+            diesel::delete(photos.filter(id.eq_any(chunk)))
+                .execute(&conn)?;
+            diesel::delete(shares::table.filter(shares::photo_id.eq_any(chunk)))
+                .execute(&conn)?;
+            diesel::update(photos.filter(primary_photo_id.eq_any(chunk)))
+                .set(primary_photo_id.eq(None::<String>))
+                .execute(&conn)?;
+        }
+
+        debug!("Removed {} stale photos from database.", stale_ids.len());
+
+        Ok(stale_ids.len())
+    }
+}
+
+impl Handler<StackPhotos> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: StackPhotos, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+        let conn = self.conn.get().unwrap();
+
+        let mut stacks: HashMap<String, Vec<Photo>> = HashMap::new();
+        let mut unstacked_ids = Vec::new();
+        for chunk in msg.photo_ids.chunks(MAX_BOUND_PARAMETERS) {
+            for photo in photos.filter(id.eq_any(chunk)).load::<Photo>(&conn)? {
+                match photo.get_stack_name() {
+                    Some(stack_name) => stacks.entry(stack_name).or_default().push(photo),
+                    None => unstacked_ids.push(photo.id),
+                }
+            }
+        }
+
+        conn.transaction::<_, DieselError, _>(|| {
+            for chunk in unstacked_ids.chunks(MAX_BOUND_PARAMETERS) {
+                diesel::update(photos.filter(id.eq_any(chunk)))
+                    .set(primary_photo_id.eq(None::<String>))
+                    .execute(&conn)?;
+            }
+
+            for stack in stacks.values_mut() {
+                // RAW files make poor display images, keep them as variants.
+                stack.sort_by(|a, b| (a.is_raw(), &a.name).cmp(&(b.is_raw(), &b.name)));
+                let primary_id = &stack[0].id;
+
+                diesel::update(photos.find(primary_id))
+                    .set(primary_photo_id.eq(None::<String>))
+                    .execute(&conn)?;
+
+                let variant_ids: Vec<&String> = stack[1..].iter().map(|photo| &photo.id).collect();
+                diesel::update(photos.filter(id.eq_any(variant_ids)))
+                    .set(primary_photo_id.eq(primary_id))
+                    .execute(&conn)?;
+            }
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Handler<GetPhotoVariants> for DbExecutor {
+    type Result = Result<Vec<Photo>, GalleryError>;
+
+    fn handle(&mut self, msg: GetPhotoVariants, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+
+        let variants = photos
+            .filter(primary_photo_id.eq(&msg.photo_id))
+            .order(name.asc())
+            .load::<Photo>(&self.conn.get().unwrap())?;
+
+        Ok(variants)
     }
 }
 
@@ -63,25 +257,60 @@ impl Handler<GetAdjacentPhotos> for DbExecutor {
     type Result = Result<(Option<String>, Option<String>), GalleryError>;
 
     fn handle(&mut self, msg: GetAdjacentPhotos, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::albums;
         use crate::models::schema::photos::dsl::*;
         let conn = self.conn.get().unwrap();
 
-        let previous = photos.filter(album_id.eq(&msg.album_id))
-            .filter(name.lt(&msg.name))
-            .select(name)
-            .order(name.desc())
-            .limit(1)
-            .load::<String>(&conn)?
-            .pop();
+        let sort = match msg.sort {
+            Some(sort) => sort,
+            None => albums::table.find(&msg.album_id).first::<Album>(&conn)?.get_sort(),
+        };
+        let (current_date, current_mtime) = photos.filter(album_id.eq(&msg.album_id))
+            .filter(name.eq(&msg.name))
+            .select((creation_date, file_mtime))
+            .first::<(Option<NaiveDateTime>, i64)>(&conn)?;
 
-        let next = photos.filter(album_id.eq(&msg.album_id))
-            .filter(name.gt(&msg.name))
+        let album_photos = || photos.filter(album_id.eq(&msg.album_id))
+            .filter(primary_photo_id.is_null())
             .select(name)
-            .order(name.asc())
-            .limit(1)
-            .load::<String>(&conn)?
-            .pop();
+            .into_boxed();
 
-        Ok((previous, next))
+        // Photos coming before and after the current one in the ascending
+        // order, missing dates coming first.
+        let (before, after) = match sort {
+            PhotoSort::NameAsc | PhotoSort::NameDesc => (
+                album_photos().filter(name.lt(&msg.name)),
+                album_photos().filter(name.gt(&msg.name)),
+            ),
+            PhotoSort::DateAsc | PhotoSort::DateDesc => match current_date {
+                Some(ref current_date) => (
+                    album_photos().filter(creation_date.lt(current_date)
+                        .or(creation_date.is_null())
+                        .or(creation_date.eq(current_date).and(name.lt(&msg.name)))),
+                    album_photos().filter(creation_date.gt(current_date)
+                        .or(creation_date.eq(current_date).and(name.gt(&msg.name)))),
+                ),
+                None => (
+                    album_photos().filter(creation_date.is_null().and(name.lt(&msg.name))),
+                    album_photos().filter(creation_date.is_not_null().or(name.gt(&msg.name))),
+                ),
+            },
+            PhotoSort::MtimeAsc | PhotoSort::MtimeDesc => (
+                album_photos().filter(file_mtime.lt(current_mtime)
+                    .or(file_mtime.eq(current_mtime).and(name.lt(&msg.name)))),
+                album_photos().filter(file_mtime.gt(current_mtime)
+                    .or(file_mtime.eq(current_mtime).and(name.gt(&msg.name)))),
+            ),
+        };
+        // The nearest photos first.
+        let (previous, next) = if sort.is_descending() {
+            (order_photos(after, sort.reversed()), order_photos(before, sort))
+        } else {
+            (order_photos(before, sort.reversed()), order_photos(after, sort))
+        };
+        Ok((
+            previous.limit(1).load::<String>(&conn)?.pop(),
+            next.limit(1).load::<String>(&conn)?.pop(),
+        ))
     }
 }
@@ -1,22 +1,49 @@
 use actix_web::actix::Handler;
 use diesel;
 use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
 
 use crate::models::db::{DbExecutor};
-use crate::models::photo_thumbnail::{PhotoThumbnail, GetPhotosThumbnail};
+use crate::models::schema::photos;
+use crate::models::photo_thumbnail::{PhotoThumbnail, PhotoSort, GetPhotosThumbnail};
 use crate::error::GalleryError;
 
+/// Orders the photos of a query, ties being broken by their name so that
+/// the order is stable between the pages.
+pub fn order_photos<'a, ST>(query: photos::BoxedQuery<'a, Sqlite, ST>, sort: PhotoSort) -> photos::BoxedQuery<'a, Sqlite, ST> {
+    use crate::models::schema::photos::dsl::*;
+
+    match sort {
+        PhotoSort::NameAsc => query.order(name.asc()),
+        PhotoSort::NameDesc => query.order(name.desc()),
+        PhotoSort::DateAsc => query.order((creation_date.asc(), name.asc())),
+        PhotoSort::DateDesc => query.order((creation_date.desc(), name.desc())),
+        PhotoSort::MtimeAsc => query.order((file_mtime.asc(), name.asc())),
+        PhotoSort::MtimeDesc => query.order((file_mtime.desc(), name.desc())),
+    }
+}
+
 impl Handler<GetPhotosThumbnail> for DbExecutor {
-    type Result = Result<Vec<PhotoThumbnail>, GalleryError>;
+    type Result = Result<(Vec<PhotoThumbnail>, i64), GalleryError>;
 
     fn handle(&mut self, msg: GetPhotosThumbnail, _ctx: &mut Self::Context) -> Self::Result {
         use crate::models::schema::photos::dsl::*;
 
-        let thumbnails = photos.filter(album_id.eq(msg.parent_album_id))
-            .select((name, creation_date))
-            .order(name.asc())
-            .load::<PhotoThumbnail>(&self.conn.get().unwrap())?;
+        let conn = self.conn.get().unwrap();
+        let query = photos.filter(album_id.eq(&msg.parent_album_id))
+            .filter(primary_photo_id.is_null())
+            .select((name, creation_date, mime_type, width, height, hash, file_mtime))
+            .into_boxed();
+        let thumbnails = order_photos(query, msg.sort)
+            .offset(msg.offset)
+            .limit(msg.limit)
+            .load::<PhotoThumbnail>(&conn)?;
+
+        let total = photos.filter(album_id.eq(&msg.parent_album_id))
+            .filter(primary_photo_id.is_null())
+            .count()
+            .get_result::<i64>(&conn)?;
 
-        Ok(thumbnails)
+        Ok((thumbnails, total))
     }
 }
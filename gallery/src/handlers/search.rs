@@ -0,0 +1,128 @@
+use std::collections::{BTreeSet, HashMap};
+
+use actix_web::actix::Handler;
+use diesel;
+use diesel::prelude::*;
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Text};
+use diesel::sqlite::Sqlite;
+
+use crate::models::db::{DbExecutor, MAX_BOUND_PARAMETERS};
+use crate::models::schema::photos;
+use crate::models::photo_thumbnail::{PhotoThumbnail, PhotoSort};
+use crate::models::search::{SearchPhotos, SearchResult, SearchResults, SearchFilters};
+use crate::handlers::album::get_browsable_albums;
+use crate::handlers::photo_thumbnail::order_photos;
+use crate::error::GalleryError;
+
+impl Handler<SearchPhotos> for DbExecutor {
+    type Result = Result<SearchResults, GalleryError>;
+
+    fn handle(&mut self, msg: SearchPhotos, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+        use crate::models::schema::{photos_search, photos_search_keys};
+
+        let conn = self.conn.get().unwrap();
+        let album_urls = get_browsable_albums(&conn, &msg.viewer)?;
+
+        let mut cameras = BTreeSet::new();
+        let mut apertures = BTreeSet::new();
+        let mut focal_lengths = BTreeSet::new();
+        let values = photos
+            .filter(primary_photo_id.is_null())
+            .select((album_id, camera, aperture, focal_length))
+            .distinct()
+            .load::<(String, Option<String>, Option<String>, Option<String>)>(&conn)?;
+        for (photo_album_id, photo_camera, photo_aperture, photo_focal_length) in values {
+            if album_urls.contains_key(&photo_album_id) {
+                cameras.extend(photo_camera);
+                apertures.extend(photo_aperture);
+                focal_lengths.extend(photo_focal_length);
+            }
+        }
+        let filters = SearchFilters::new(
+            cameras.into_iter().collect(),
+            apertures.into_iter().collect(),
+            focal_lengths.into_iter().collect(),
+        );
+
+        if msg.query.is_empty() {
+            return Ok(SearchResults { photos: Vec::new(), total: 0, filters });
+        }
+
+        // The photos matching are filtered by album in memory, the access
+        // to the albums depending on their parents.
+        let mut query = photos
+            .filter(primary_photo_id.is_null())
+            .select((id, album_id))
+            .into_boxed();
+        if let Some(match_query) = msg.query.get_match_query() {
+            let matching_keys = photos_search::table
+                .select(photos_search::rowid)
+                .filter(sql::<Bool>("photos_search MATCH ").bind::<Text, _>(match_query));
+            let matching_ids = photos_search_keys::table
+                .select(photos_search_keys::photo_id)
+                .filter(photos_search_keys::id.eq_any(matching_keys));
+            query = query.filter(id.eq_any(matching_ids));
+        }
+        if let Some(ref value) = msg.query.camera {
+            query = query.filter(camera.eq(value));
+        }
+        if let Some(ref value) = msg.query.aperture {
+            query = query.filter(aperture.eq(value));
+        }
+        if let Some(ref value) = msg.query.focal_length {
+            query = query.filter(focal_length.eq(value));
+        }
+        if let Some(date_from) = msg.query.get_date_from() {
+            query = query.filter(creation_date.ge(date_from));
+        }
+        if let Some(date_until) = msg.query.get_date_until() {
+            query = query.filter(creation_date.lt(date_until));
+        }
+        let (results, total) = load_photo_page(&conn, query, msg.sort, msg.offset, msg.limit, &album_urls)?;
+
+        Ok(SearchResults { photos: results, total, filters })
+    }
+}
+
+/// Page of the photos found by a query of their ids and album ids, the ones
+/// in albums the viewer can't browse being left out, with the number of
+/// photos found.
+pub fn load_photo_page(conn: &SqliteConnection, query: photos::BoxedQuery<Sqlite, (Text, Text)>, sort: PhotoSort, offset: i64, limit: i64,
+    album_urls: &HashMap<String, String>)
+    -> Result<(Vec<SearchResult>, i64), GalleryError>
+{
+    use crate::models::schema::photos::dsl::*;
+
+    let matching_ids: Vec<String> = order_photos(query, sort)
+        .load::<(String, String)>(conn)?
+        .into_iter()
+        .filter(|(_, photo_album_id)| album_urls.contains_key(photo_album_id))
+        .map(|(photo_id, _)| photo_id)
+        .collect();
+
+    let page_ids: Vec<&String> = matching_ids.iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+    let mut page_photos = HashMap::new();
+    for chunk in page_ids.chunks(MAX_BOUND_PARAMETERS) {
+        let chunk_photos = photos
+            .filter(id.eq_any(chunk))
+            .select((id, album_id, (name, creation_date, mime_type, width, height, hash, file_mtime)))
+            .load::<(String, String, PhotoThumbnail)>(conn)?;
+        for (photo_id, photo_album_id, photo) in chunk_photos {
+            page_photos.insert(photo_id, (photo_album_id, photo));
+        }
+    }
+    let results = page_ids.into_iter()
+        .filter_map(|photo_id| page_photos.remove(photo_id))
+        .map(|(photo_album_id, photo)| SearchResult {
+            album_url: album_urls[&photo_album_id].clone(),
+            photo,
+        })
+        .collect();
+
+    Ok((results, matching_ids.len() as i64))
+}
@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use actix_web::actix::Handler;
+use uuid;
+use diesel;
+use diesel::prelude::*;
+use chrono::Utc;
+
+use crate::models::db::DbExecutor;
+use crate::models::share::{Share, ShareListing, CreateShare, GetActiveShares, GetShareByToken, RevokeShare, SECRET_KEY_SETTING};
+use crate::models::user::Session;
+use crate::handlers::album::{find_album, get_album_path};
+use crate::utils::encode_url_path;
+use crate::error::GalleryError;
+
+/// Key signing the share links, created along the first one.
+pub fn get_secret_key(conn: &SqliteConnection) -> Result<Vec<u8>, GalleryError> {
+    use crate::models::schema::settings;
+
+    let secret_key = settings::table
+        .find(SECRET_KEY_SETTING)
+        .select(settings::value)
+        .first::<String>(conn)
+        .optional()?;
+
+    let secret_key = match secret_key {
+        Some(secret_key) => secret_key,
+        None => {
+            let secret_key = Session::generate_token();
+            diesel::insert_into(settings::table)
+                .values((settings::name.eq(SECRET_KEY_SETTING), settings::value.eq(&secret_key)))
+                .execute(conn)?;
+            secret_key
+        },
+    };
+    Ok(secret_key.into_bytes())
+}
+
+/// Shares of the tokens held by a viewer which are valid and still active.
+pub fn get_active_shares(conn: &SqliteConnection, tokens: &[String]) -> Result<Vec<Share>, GalleryError> {
+    use crate::models::schema::shares;
+
+    let secret_key = get_secret_key(conn)?;
+    let share_ids: Vec<&str> = tokens.iter()
+        .filter_map(|token| Share::verify_token(token, &secret_key))
+        .collect();
+    if share_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let now = Utc::now().naive_utc();
+    let active_shares = shares::table
+        .filter(shares::id.eq_any(share_ids))
+        .load::<Share>(conn)?
+        .into_iter()
+        .filter(|share| share.is_active(now))
+        .collect();
+    Ok(active_shares)
+}
+
+fn get_listing(conn: &SqliteConnection, share: Share, secret_key: &[u8]) -> Result<ShareListing, GalleryError> {
+    use crate::models::schema::photos;
+
+    let mut path = get_album_path(conn, &share.album_id)?;
+    if let Some(ref photo_id) = share.photo_id {
+        path.push(photos::table.find(photo_id).select(photos::name).first::<String>(conn)?);
+    }
+    let path = path.to_string_lossy().into_owned();
+    let url = format!("/{}?share={}", encode_url_path(&path), share.get_token(secret_key));
+
+    Ok(ShareListing { share, path, url })
+}
+
+impl Handler<CreateShare> for DbExecutor {
+    type Result = Result<ShareListing, GalleryError>;
+
+    fn handle(&mut self, msg: CreateShare, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::{photos, shares};
+
+        let conn = self.conn.get().unwrap();
+
+        // The path is either the one of an album, or of a photo in one.
+        let (album_id, photo_id) = match find_album(&conn, &msg.path) {
+            Ok(album) => (album.id, None),
+            Err(GalleryError::NotFound) => {
+                let album = find_album(&conn, msg.path.parent().unwrap_or_else(|| Path::new("")))?;
+                let photo_name = msg.path.file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or(GalleryError::NotFound)?;
+                let photo_id = photos::table
+                    .filter(photos::album_id.eq(&album.id))
+                    .filter(photos::name.eq(photo_name))
+                    .select(photos::id)
+                    .first::<String>(&conn)?;
+                (album.id, Some(photo_id))
+            },
+            Err(e) => return Err(e),
+        };
+
+        let share = Share {
+            id: uuid::Uuid::new_v4().to_string(),
+            album_id,
+            photo_id,
+            label: msg.label,
+            allow_download: msg.allow_download,
+            created_at: Utc::now().naive_utc(),
+            expires_at: msg.expires_at,
+            revoked_at: None,
+        };
+        diesel::insert_into(shares::table)
+            .values(&share)
+            .execute(&conn)?;
+
+        info!("Shared {:?} as {}.", msg.path, share.id);
+        get_listing(&conn, share, &get_secret_key(&conn)?)
+    }
+}
+
+impl Handler<GetActiveShares> for DbExecutor {
+    type Result = Result<Vec<ShareListing>, GalleryError>;
+
+    fn handle(&mut self, _msg: GetActiveShares, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::shares;
+
+        let conn = self.conn.get().unwrap();
+        let now = Utc::now().naive_utc();
+        let secret_key = get_secret_key(&conn)?;
+
+        shares::table
+            .filter(shares::revoked_at.is_null())
+            .order(shares::created_at.desc())
+            .load::<Share>(&conn)?
+            .into_iter()
+            .filter(|share| share.is_active(now))
+            .map(|share| get_listing(&conn, share, &secret_key))
+            .collect()
+    }
+}
+
+impl Handler<GetShareByToken> for DbExecutor {
+    type Result = Result<Option<Share>, GalleryError>;
+
+    fn handle(&mut self, msg: GetShareByToken, _ctx: &mut Self::Context) -> Self::Result {
+        let conn = self.conn.get().unwrap();
+        Ok(get_active_shares(&conn, &[msg.token])?.pop())
+    }
+}
+
+impl Handler<RevokeShare> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: RevokeShare, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::shares::dsl::*;
+
+        let conn = self.conn.get().unwrap();
+        let revoked = diesel::update(shares.find(&msg.share_id).filter(revoked_at.is_null()))
+            .set(revoked_at.eq(Utc::now().naive_utc()))
+            .execute(&conn)?;
+        if revoked == 0 {
+            return Err(GalleryError::NotFound);
+        }
+
+        info!("Revoked share {}.", msg.share_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::actix::Handler;
+use diesel;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable, Text};
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::models::db::{DbExecutor, MAX_BOUND_PARAMETERS};
+use crate::models::photo_thumbnail::PhotoSort;
+use crate::models::timeline::{Timeline, TimelineDay, TimelineDayResult, GetTimeline, GetTimelineDay};
+use crate::handlers::album::get_browsable_albums;
+use crate::handlers::search::load_photo_page;
+use crate::error::GalleryError;
+
+/// Number of photos of an album taken on a day.
+#[derive(QueryableByName)]
+struct DayCount {
+    #[sql_type = "Nullable<Text>"]
+    day: Option<String>,
+    #[sql_type = "Text"]
+    album_id: String,
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+fn load_timeline(conn: &SqliteConnection, album_urls: &HashMap<String, String>) -> Result<Timeline, GalleryError> {
+    let day_counts = diesel::sql_query(
+        "SELECT date(creation_date) AS day, album_id, count(*) AS count FROM photos \
+         WHERE primary_photo_id IS NULL GROUP BY day, album_id"
+    ).load::<DayCount>(conn)?;
+
+    let mut days = BTreeMap::new();
+    let mut undated_count = 0;
+    for day_count in day_counts.into_iter().filter(|day_count| album_urls.contains_key(&day_count.album_id)) {
+        match day_count.day.and_then(|day| NaiveDate::parse_from_str(&day, "%Y-%m-%d").ok()) {
+            Some(date) => *days.entry(date).or_insert(0) += day_count.count,
+            None => undated_count += day_count.count,
+        }
+    }
+
+    Ok(Timeline {
+        days: days.into_iter().map(|(date, count)| TimelineDay { date, count }).collect(),
+        undated_count,
+    })
+}
+
+/// Closest day before or after the given one with photos the viewer can
+/// browse, found through the index of the creation dates rather than by
+/// loading the whole timeline.
+fn find_adjacent_day(conn: &SqliteConnection, date: NaiveDate, is_next: bool, album_urls: &HashMap<String, String>)
+    -> Result<Option<TimelineDay>, GalleryError>
+{
+    use crate::models::schema::photos::dsl::*;
+
+    let album_ids: Vec<&String> = album_urls.keys().collect();
+    let (day_start, next_day_start) = (date.and_hms(0, 0, 0), date.succ().and_hms(0, 0, 0));
+
+    let mut adjacent_date: Option<NaiveDateTime> = None;
+    for chunk in album_ids.chunks(MAX_BOUND_PARAMETERS) {
+        let query = photos
+            .filter(primary_photo_id.is_null())
+            .filter(album_id.eq_any(chunk));
+        let chunk_date = if is_next {
+            query.filter(creation_date.ge(next_day_start))
+                .select(diesel::dsl::min(creation_date))
+                .first::<Option<NaiveDateTime>>(conn)?
+        } else {
+            query.filter(creation_date.lt(day_start))
+                .select(diesel::dsl::max(creation_date))
+                .first::<Option<NaiveDateTime>>(conn)?
+        };
+        adjacent_date = match (adjacent_date, chunk_date) {
+            (Some(found), Some(chunk_date)) => Some(if is_next { found.min(chunk_date) } else { found.max(chunk_date) }),
+            (found, chunk_date) => found.or(chunk_date),
+        };
+    }
+
+    let adjacent_date = match adjacent_date {
+        Some(adjacent_date) => adjacent_date.date(),
+        None => return Ok(None),
+    };
+    let mut count = 0;
+    for chunk in album_ids.chunks(MAX_BOUND_PARAMETERS) {
+        count += photos
+            .filter(primary_photo_id.is_null())
+            .filter(album_id.eq_any(chunk))
+            .filter(creation_date.ge(adjacent_date.and_hms(0, 0, 0)))
+            .filter(creation_date.lt(adjacent_date.succ().and_hms(0, 0, 0)))
+            .count()
+            .get_result::<i64>(conn)?;
+    }
+    Ok(Some(TimelineDay { date: adjacent_date, count }))
+}
+
+impl Handler<GetTimeline> for DbExecutor {
+    type Result = Result<Timeline, GalleryError>;
+
+    fn handle(&mut self, msg: GetTimeline, _ctx: &mut Self::Context) -> Self::Result {
+        let conn = self.conn.get().unwrap();
+        let album_urls = get_browsable_albums(&conn, &msg.viewer)?;
+
+        load_timeline(&conn, &album_urls)
+    }
+}
+
+impl Handler<GetTimelineDay> for DbExecutor {
+    type Result = Result<TimelineDayResult, GalleryError>;
+
+    fn handle(&mut self, msg: GetTimelineDay, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::photos::dsl::*;
+
+        let conn = self.conn.get().unwrap();
+        let album_urls = get_browsable_albums(&conn, &msg.viewer)?;
+
+        let query = photos
+            .filter(primary_photo_id.is_null())
+            .filter(creation_date.ge(msg.date.and_hms(0, 0, 0)))
+            .filter(creation_date.lt(msg.date.succ().and_hms(0, 0, 0)))
+            .select((id, album_id))
+            .into_boxed();
+        let (results, total) = load_photo_page(&conn, query, PhotoSort::DateAsc, msg.offset, msg.limit, &album_urls)?;
+
+        Ok(TimelineDayResult {
+            photos: results,
+            total,
+            previous_day: find_adjacent_day(&conn, msg.date, false, &album_urls)?,
+            next_day: find_adjacent_day(&conn, msg.date, true, &album_urls)?,
+        })
+    }
+}
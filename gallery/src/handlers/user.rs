@@ -0,0 +1,189 @@
+use actix_web::actix::Handler;
+use uuid;
+use diesel;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use chrono::{Duration, Utc};
+
+use crate::models::db::DbExecutor;
+use crate::models::user::{
+    User, UserGroup, Session, Identity, PasswordHasher, SetPassword, GetUser, VerifyPassword, OpenSession, Logout, GetIdentity,
+};
+use crate::error::GalleryError;
+
+impl Handler<SetPassword> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: SetPassword, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::{users, sessions, user_groups};
+
+        let conn = self.conn.get().unwrap();
+        let password_hash = User::hash_password(&msg.password)?;
+
+        let existing_user = users::table
+            .filter(users::username.eq(&msg.username))
+            .first::<User>(&conn)
+            .optional()?;
+
+        conn.transaction::<_, DieselError, _>(|| {
+            let user_id = match existing_user {
+                Some(user) => {
+                    diesel::update(users::table.find(&user.id))
+                        .set(users::password_hash.eq(&password_hash))
+                        .execute(&conn)?;
+                    diesel::delete(sessions::table.filter(sessions::user_id.eq(&user.id)))
+                        .execute(&conn)?;
+                    user.id
+                },
+                None => {
+                    let user = User {
+                        id: uuid::Uuid::new_v4().to_string(),
+                        username: msg.username.clone(),
+                        password_hash,
+                    };
+                    diesel::insert_into(users::table)
+                        .values(&user)
+                        .execute(&conn)?;
+                    user.id
+                },
+            };
+
+            if let Some(ref groups) = msg.groups {
+                diesel::delete(user_groups::table.filter(user_groups::user_id.eq(&user_id)))
+                    .execute(&conn)?;
+                for group_name in groups {
+                    diesel::insert_into(user_groups::table)
+                        .values(&UserGroup { user_id: user_id.clone(), group_name: group_name.clone() })
+                        .execute(&conn)?;
+                }
+            }
+            Ok(())
+        })?;
+
+        debug!("Password of {} set.", msg.username);
+        Ok(())
+    }
+}
+
+impl Handler<GetUser> for DbExecutor {
+    type Result = Result<Option<User>, GalleryError>;
+
+    fn handle(&mut self, msg: GetUser, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::users;
+
+        let conn = self.conn.get().unwrap();
+        let user = users::table
+            .filter(users::username.eq(&msg.username))
+            .first::<User>(&conn)
+            .optional()?;
+        Ok(user)
+    }
+}
+
+impl Handler<VerifyPassword> for PasswordHasher {
+    type Result = Result<bool, GalleryError>;
+
+    fn handle(&mut self, msg: VerifyPassword, _ctx: &mut Self::Context) -> Self::Result {
+        match msg.password_hash {
+            Some(ref password_hash) => Ok(User::verify_password(password_hash, &msg.password)),
+            None => {
+                User::hash_password(&msg.password)?;
+                Ok(false)
+            },
+        }
+    }
+}
+
+impl Handler<OpenSession> for DbExecutor {
+    type Result = Result<Identity, GalleryError>;
+
+    fn handle(&mut self, msg: OpenSession, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::{sessions, user_groups};
+
+        let conn = self.conn.get().unwrap();
+        let now = Utc::now().naive_utc();
+        let user = msg.user;
+
+        diesel::delete(sessions::table.filter(sessions::expires_at.le(now)))
+            .execute(&conn)?;
+
+        let token = Session::generate_token();
+        let session = Session {
+            id: Session::hash_token(&token),
+            user_id: user.id.clone(),
+            created_at: now,
+            expires_at: now + Duration::days(msg.session_days),
+        };
+        diesel::insert_into(sessions::table)
+            .values(&session)
+            .execute(&conn)?;
+
+        let groups = user_groups::table
+            .filter(user_groups::user_id.eq(&user.id))
+            .select(user_groups::group_name)
+            .load::<String>(&conn)?;
+
+        info!("{} logged in.", user.username);
+        Ok(Identity {
+            username: user.username,
+            groups,
+            session_token: Some(token),
+        })
+    }
+}
+
+impl Handler<Logout> for DbExecutor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: Logout, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::sessions::dsl::*;
+
+        let conn = self.conn.get().unwrap();
+        diesel::delete(sessions.find(Session::hash_token(&msg.token)))
+            .execute(&conn)?;
+        Ok(())
+    }
+}
+
+impl Handler<GetIdentity> for DbExecutor {
+    type Result = Result<Option<Identity>, GalleryError>;
+
+    fn handle(&mut self, msg: GetIdentity, _ctx: &mut Self::Context) -> Self::Result {
+        use crate::models::schema::{users, sessions, user_groups};
+
+        let conn = self.conn.get().unwrap();
+        let now = Utc::now().naive_utc();
+
+        let username = match (msg.proxy_username, &msg.session_token) {
+            (Some(username), _) => username,
+            (None, Some(token)) => {
+                let username = sessions::table
+                    .inner_join(users::table)
+                    .filter(sessions::id.eq(Session::hash_token(token)))
+                    .filter(sessions::expires_at.gt(now))
+                    .select(users::username)
+                    .first::<String>(&conn)
+                    .optional()?;
+                match username {
+                    Some(username) => username,
+                    None => return Ok(None),
+                }
+            },
+            (None, None) => return Ok(None),
+        };
+
+        // Users named by the reverse proxy don't have to exist in the
+        // database, they are then in no group.
+        let groups = user_groups::table
+            .inner_join(users::table)
+            .filter(users::username.eq(&username))
+            .select(user_groups::group_name)
+            .load::<String>(&conn)?;
+
+        Ok(Some(Identity {
+            username,
+            groups,
+            session_token: msg.session_token,
+        }))
+    }
+}
@@ -2,15 +2,24 @@ use std::path::PathBuf;
 
 use actix_web::actix::{Actor, Addr, SyncContext, SyncArbiter, Handler, Message  };
 use futures::future::Future;
+use image::GenericImageView;
 
 use crate::models::db::DbExecutor;
-use crate::models::photo::{Photo, GetPhotoId, CreatePhoto};
+use crate::models::photo::{Photo, FindPhoto, GetPhotosByHash, CreatePhoto, UpdatePhoto};
+use crate::models::album::GetAlbumPath;
 use crate::models::photo_thumbnail::PhotoThumbnail;
+use crate::models::source_format::SourceFormat;
+use crate::models::video::VideoMetadata;
 use crate::models::helper::ExifExtractor;
 use crate::error::GalleryError;
 use crate::utils;
 use crate::config::Config;
 
+/// Version of what the indexer reads from the files and of the thumbnails
+/// it generates. Photos indexed by an older version are processed again,
+/// even when their file didn't change.
+pub const INDEX_VERSION: i32 = 3;
+
 pub struct IndexerActor {
     db: Addr<DbExecutor>,
     config: Config,
@@ -36,56 +45,222 @@ pub struct IndexFile {
     pub parent: String,
 }
 
+pub struct IndexedFile {
+    pub photo_id: String,
+    /// False when the photo was already up to date in the index.
+    pub updated: bool,
+}
+
 impl Message for IndexFile {
-    type Result = Result<(), GalleryError>;
+    type Result = Result<IndexedFile, GalleryError>;
 }
 
 impl Handler<IndexFile> for IndexerActor {
-    type Result = Result<(), GalleryError>;
+    type Result = Result<IndexedFile, GalleryError>;
 
     fn handle(&mut self, msg: IndexFile, _ctx: &mut Self::Context) -> Self::Result {
         info!("Indexing file {:?}", msg.path);
-        let is_valid_extension = msg.path.extension()
-            .map(|ext| self.config
-                    .allowed_extensions
-                    .contains(&ext.to_string_lossy().to_string().to_lowercase()));
-
-        if is_valid_extension != Some(true) {
-            warn!("Invalid extension, skipping file");
-            return Err(GalleryError::InvalidFileName);
-        }
+        let format = match SourceFormat::detect(&msg.path)? {
+            Some(format) => format,
+            None => {
+                warn!("Unsupported format, skipping file");
+                return Err(GalleryError::UnsupportedFormat);
+            },
+        };
 
-        let name = utils::get_file_name_string(&msg.path)?;
+        let mut photo = Photo {
+            name: utils::get_file_name_string(&msg.path)?,
+            album_id: msg.parent,
+            ..Default::default()
+        };
+        photo.read_file_state(&msg.path)?;
 
-        let photo_id = self.db.send(GetPhotoId {
-            name: name.clone(),
-            album_id: msg.parent.clone(),
+        let existing_photo = self.db.send(FindPhoto {
+            name: photo.name.clone(),
+            album_id: photo.album_id.clone(),
         }).wait()??;
 
-        if let Some(_) = photo_id {
-            debug!("Already in index.");
+        if let Some(mut existing_photo) = existing_photo {
+            let outdated = existing_photo.index_version < INDEX_VERSION;
+            if existing_photo.has_same_file_state(&photo) && !outdated {
+                debug!("Already in index.");
+                return Ok(IndexedFile { photo_id: existing_photo.id, updated: false });
+            }
+
+            photo.hash = Photo::compute_hash(&msg.path)?;
+            let same_content = photo.hash == existing_photo.hash;
+            if same_content && !outdated {
+                debug!("Content unchanged, only updating file state.");
+                existing_photo.file_size = photo.file_size;
+                existing_photo.file_mtime = photo.file_mtime;
+                return self.update_photo(existing_photo, false);
+            }
+
+            if same_content {
+                debug!("Indexed by an older version, indexing it again.");
+            } else {
+                debug!("File changed, indexing it again.");
+            }
+            photo.id = existing_photo.id;
+            // The thumbnails named after the same hash are outdated as well.
+            self.process_photo(&msg.path, format, &mut photo, same_content)?;
+            return self.update_photo(photo, true);
+        }
+
+        photo.hash = Photo::compute_hash(&msg.path)?;
+
+        if let Some(mut moved_photo) = self.find_moved_photo(&photo.hash)? {
+            debug!("Photo moved from album {} as {:?}, relocating it.", moved_photo.album_id, moved_photo.name);
+            if moved_photo.index_version < INDEX_VERSION {
+                debug!("Indexed by an older version, indexing it again.");
+                photo.id = moved_photo.id;
+                self.process_photo(&msg.path, format, &mut photo, true)?;
+                return self.update_photo(photo, true);
+            }
+            moved_photo.name = photo.name;
+            moved_photo.album_id = photo.album_id;
+            moved_photo.file_size = photo.file_size;
+            moved_photo.file_mtime = photo.file_mtime;
+            return self.update_photo(moved_photo, true);
+        }
+
+        self.process_photo(&msg.path, format, &mut photo, false)?;
+        let photo_id = self.db.send(CreatePhoto { photo }).wait()??;
+
+        Ok(IndexedFile { photo_id, updated: true })
+    }
+}
+
+impl IndexerActor {
+    /// Reads the metadata of the file and generates its thumbnails, replacing
+    /// the existing ones when asked to.
+    fn process_photo(&self, path: &PathBuf, format: SourceFormat, photo: &mut Photo, replace_thumbnails: bool) -> Result<(), GalleryError> {
+        photo.extract_exif(path)?;
+        photo.camera = photo.camera.take().map(utils::trim_one_char);
+        photo.mime_type = Some(format.mime_type().to_string());
+        photo.index_version = INDEX_VERSION;
+
+        if let SourceFormat::Video(_) = format {
+            let metadata = VideoMetadata::probe(path, &self.config.decoders)?;
+            photo.duration = metadata.duration;
+            photo.creation_date = photo.creation_date.take().or(metadata.creation_date);
+            photo.width = metadata.width;
+            photo.height = metadata.height;
+        }
+
+        if self.config.render.on_demand {
+            // The thumbnails are generated when first requested.
+            if replace_thumbnails {
+                for thumbnail_config in self.config.thumbnails.values() {
+                    PhotoThumbnail::remove_image(&photo.hash, thumbnail_config, self.config.cache_path.clone())?;
+                }
+            }
+            if let Some((width, height)) = format.read_dimensions(path, photo.orientation, &self.config.decoders)? {
+                photo.width = Some(width as i32);
+                photo.height = Some(height as i32);
+            }
             return Ok(());
         }
 
+        let img = format.decode(path, photo.orientation, &self.config.decoders)?;
+        let (width, height) = img.dimensions();
+        photo.width = Some(width as i32);
+        photo.height = Some(height as i32);
+
         debug!("Generating thumbnails...");
-        let hash = Photo::compute_hash(&msg.path)?;
+        for thumbnail_config in self.config.thumbnails.values() {
+            if replace_thumbnails {
+                let thumbnail_path = PhotoThumbnail::get_image_path(&photo.hash, thumbnail_config, self.config.cache_path.clone());
+                PhotoThumbnail::generate_image(&img, thumbnail_path, thumbnail_config, &self.config.encoders)?;
+            } else {
+                PhotoThumbnail::create_image(&img, &photo.hash, thumbnail_config, &self.config.encoders, self.config.cache_path.clone())?;
+            }
+        }
+
+        Ok(())
+    }
 
-        for (_, thumbnail_config) in &self.config.thumbnails {
-            PhotoThumbnail::create_image(&msg.path, &hash, &thumbnail_config, self.config.cache_path.clone())?;
+    /// Look for an indexed photo with the same content whose source file
+    /// disappeared, meaning it was moved or renamed since the last index.
+    fn find_moved_photo(&self, hash: &str) -> Result<Option<Photo>, GalleryError> {
+        let candidates = self.db.send(GetPhotosByHash { hash: hash.to_string() }).wait()??;
+
+        for candidate in candidates {
+            let album_path = self.db.send(GetAlbumPath {
+                album_id: candidate.album_id.clone(),
+            }).wait()??;
+
+            let source_path = utils::get_album_canonical_path(album_path.join(&candidate.name), &self.config);
+            if !source_path.is_file() {
+                return Ok(Some(candidate));
+            }
         }
 
-        let mut photo = Photo {
-            name,
-            hash,
-            album_id: msg.parent,
-            ..Default::default()
-        };
+        Ok(None)
+    }
+
+    fn update_photo(&self, photo: Photo, updated: bool) -> Result<IndexedFile, GalleryError> {
+        let photo_id = photo.id.clone();
+        self.db.send(UpdatePhoto { photo }).wait()??;
+        Ok(IndexedFile { photo_id, updated })
+    }
+}
+
+pub struct RebuildThumbnails {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+impl Message for RebuildThumbnails {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Handler<RebuildThumbnails> for IndexerActor {
+    type Result = Result<(), GalleryError>;
 
+    fn handle(&mut self, msg: RebuildThumbnails, _ctx: &mut Self::Context) -> Self::Result {
+        debug!("Rebuilding thumbnails of {:?}", msg.path);
+        if self.config.render.on_demand {
+            // Rendered again when next requested.
+            for thumbnail_config in self.config.thumbnails.values() {
+                PhotoThumbnail::remove_image(&msg.hash, thumbnail_config, self.config.cache_path.clone())?;
+            }
+            return Ok(());
+        }
+
+        let format = SourceFormat::detect(&msg.path)?.ok_or(GalleryError::UnsupportedFormat)?;
+        let mut photo = Photo::default();
         photo.extract_exif(&msg.path)?;
-        photo.camera = photo.camera.map(utils::trim_one_char);
 
-        self.db.send(CreatePhoto { photo: photo }).wait()??;
+        let img = format.decode(&msg.path, photo.orientation, &self.config.decoders)?;
+        for thumbnail_config in self.config.thumbnails.values() {
+            let thumbnail_path = PhotoThumbnail::get_image_path(&msg.hash, thumbnail_config, self.config.cache_path.clone());
+            PhotoThumbnail::generate_image(&img, thumbnail_path, thumbnail_config, &self.config.encoders)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct VerifyFile {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+impl Message for VerifyFile {
+    type Result = Result<(), GalleryError>;
+}
 
+impl Handler<VerifyFile> for IndexerActor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: VerifyFile, _ctx: &mut Self::Context) -> Self::Result {
+        debug!("Verifying {:?}", msg.path);
+        if !msg.path.is_file() {
+            return Err(GalleryError::IntegrityError("source file is missing"));
+        }
+        if Photo::compute_hash(&msg.path)? != msg.hash {
+            return Err(GalleryError::IntegrityError("content does not match the indexed hash"));
+        }
         Ok(())
     }
 }
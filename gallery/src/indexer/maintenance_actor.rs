@@ -0,0 +1,230 @@
+use std::fs;
+use std::path::PathBuf;
+use std::collections::HashMap;
+
+use actix_web::actix::{Actor, Addr, Arbiter, Context, Handler, Message};
+use actix_web::actix::dev::ToEnvelope;
+use futures::future::{Future, join_all};
+
+use crate::models::album::GetAlbumPath;
+use crate::models::photo::{GetAllPhotos, GetPhotoHashes};
+use crate::models::photo_thumbnail::PhotoThumbnail;
+use crate::models::job::{Job, ChangeState, UpdateProgress, LogError, JobProgress, self};
+use crate::models::db::DbExecutor;
+use crate::config::Config;
+use crate::error::GalleryError;
+use crate::utils;
+use crate::indexer::indexer_actor::{IndexerActor, RebuildThumbnails, VerifyFile};
+
+/// Number of files processed between two checks for cancellation.
+const BATCH_SIZE: usize = 64;
+
+/// Runs the jobs working on the photos already in the index and on the cache.
+pub struct MaintenanceActor {
+    db: Addr<DbExecutor>,
+    indexer: Addr<IndexerActor>,
+    config: Config,
+}
+
+impl Actor for MaintenanceActor {
+    type Context = Context<Self>;
+}
+
+impl MaintenanceActor {
+    pub fn init(db: Addr<DbExecutor>, indexer: Addr<IndexerActor>, config: Config) -> Addr<Self> {
+        Arbiter::start(move |_ctx| {
+            MaintenanceActor {
+                db,
+                indexer,
+                config
+            }
+        })
+    }
+
+    fn start_job(&self, job_id: &str) -> Result<(), GalleryError> {
+        Job::check_cancelled(job_id, &self.db)?;
+
+        self.db.send(ChangeState {
+            job_id: job_id.to_string(),
+            new_state: job::STATE_RUNNING.to_string(),
+        }).wait()?
+    }
+
+    fn report_progress(&self, job_id: &str, progress: &JobProgress) {
+        self.db.do_send(UpdateProgress {
+            job_id: job_id.to_string(),
+            progress: progress.clone(),
+        });
+    }
+
+    fn log_error(&self, job_id: &str, path: &PathBuf, err: &GalleryError) {
+        error!("Error processing {:?}, {}", path, err);
+        self.db.do_send(LogError {
+            job_id: job_id.to_string(),
+            path: Some(path.to_string_lossy().to_string()),
+            message: err.to_string(),
+        });
+    }
+
+    /// Sends a message built from each indexed photo and its source file to
+    /// the indexer, keeping track of the outcome in the job.
+    fn process_photos<M, F>(&self, job_id: &str, build_message: F) -> Result<(), GalleryError>
+    where
+        M: Message<Result = Result<(), GalleryError>> + Send + 'static,
+        IndexerActor: Handler<M>,
+        <IndexerActor as Actor>::Context: ToEnvelope<IndexerActor, M>,
+        F: Fn(PathBuf, String) -> M,
+    {
+        let photos = self.db.send(GetAllPhotos).wait()??;
+        let mut album_paths = HashMap::new();
+        let mut sources = Vec::with_capacity(photos.len());
+
+        for photo in photos {
+            if !album_paths.contains_key(&photo.album_id) {
+                let album_path = self.db.send(GetAlbumPath {
+                    album_id: photo.album_id.clone(),
+                }).wait()??;
+                album_paths.insert(photo.album_id.clone(), utils::get_album_canonical_path(album_path, &self.config));
+            }
+            sources.push((album_paths[&photo.album_id].join(&photo.name), photo.hash));
+        }
+
+        let mut progress = JobProgress::default();
+        for batch in sources.chunks(BATCH_SIZE) {
+            Job::check_cancelled(job_id, &self.db)?;
+
+            let results = join_all(batch.iter().map(|(path, hash)| {
+                self.indexer.send(build_message(path.clone(), hash.clone()))
+            })).wait()?;
+
+            for ((path, _), result) in batch.iter().zip(results) {
+                progress.files_seen += 1;
+                match result {
+                    Ok(()) => progress.files_processed += 1,
+                    Err(err) => {
+                        progress.files_failed += 1;
+                        self.log_error(job_id, path, &err);
+                    }
+                }
+            }
+            self.report_progress(job_id, &progress);
+        }
+        Ok(())
+    }
+
+    fn rebuild_thumbnails(&self, job_id: &str) -> Result<(), GalleryError> {
+        self.start_job(job_id)?;
+        info!("Rebuilding all thumbnails.");
+
+        self.process_photos(job_id, |path, hash| RebuildThumbnails { path, hash })
+    }
+
+    fn verify_integrity(&self, job_id: &str) -> Result<(), GalleryError> {
+        self.start_job(job_id)?;
+        info!("Verifying the integrity of the photos.");
+
+        self.process_photos(job_id, |path, hash| VerifyFile { path, hash })
+    }
+
+    fn purge_cache(&self, job_id: &str) -> Result<(), GalleryError> {
+        self.start_job(job_id)?;
+        info!("Removing orphaned thumbnails from the cache.");
+
+        let hashes = self.db.send(GetPhotoHashes).wait()??;
+        let mut progress = JobProgress::default();
+
+        for (index, entry) in fs::read_dir(&self.config.cache_path)?.enumerate() {
+            if index % BATCH_SIZE == 0 {
+                Job::check_cancelled(job_id, &self.db)?;
+                self.report_progress(job_id, &progress);
+            }
+
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            progress.files_seen += 1;
+
+            let name = match utils::get_file_name_string(&path) {
+                Ok(name) => name,
+                Err(err) => {
+                    warn!("Skipping {:?}, {}", path, err);
+                    progress.files_skipped += 1;
+                    continue;
+                },
+            };
+
+            // Only the thumbnails of photos no longer indexed are removed, the
+            // cache directory may hold other files.
+            match PhotoThumbnail::get_hash_from_name(&name, &self.config) {
+                Some(hash) if !hashes.contains(hash) => (),
+                _ => {
+                    progress.files_skipped += 1;
+                    continue;
+                },
+            }
+
+            debug!("Removing {:?}", path);
+            match fs::remove_file(&path) {
+                Ok(()) => progress.files_processed += 1,
+                Err(err) => {
+                    progress.files_failed += 1;
+                    self.log_error(job_id, &path, &GalleryError::from(err));
+                }
+            }
+        }
+        self.report_progress(job_id, &progress);
+        Ok(())
+    }
+}
+
+pub struct StartRebuildThumbnails {
+    pub job_id: String,
+}
+
+pub struct StartPurgeCache {
+    pub job_id: String,
+}
+
+pub struct StartVerifyIntegrity {
+    pub job_id: String,
+}
+
+impl Message for StartRebuildThumbnails {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for StartPurgeCache {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for StartVerifyIntegrity {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Handler<StartRebuildThumbnails> for MaintenanceActor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: StartRebuildThumbnails, _ctx: &mut Self::Context) -> Self::Result {
+        let result = self.rebuild_thumbnails(&msg.job_id);
+        Job::report_outcome(msg.job_id, result, &self.db)
+    }
+}
+
+impl Handler<StartPurgeCache> for MaintenanceActor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: StartPurgeCache, _ctx: &mut Self::Context) -> Self::Result {
+        let result = self.purge_cache(&msg.job_id);
+        Job::report_outcome(msg.job_id, result, &self.db)
+    }
+}
+
+impl Handler<StartVerifyIntegrity> for MaintenanceActor {
+    type Result = Result<(), GalleryError>;
+
+    fn handle(&mut self, msg: StartVerifyIntegrity, _ctx: &mut Self::Context) -> Self::Result {
+        let result = self.verify_integrity(&msg.job_id);
+        Job::report_outcome(msg.job_id, result, &self.db)
+    }
+}
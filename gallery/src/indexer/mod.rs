@@ -1,2 +1,6 @@
 pub mod indexer_actor;
 pub mod walker_actor;
+pub mod watcher_actor;
+pub mod maintenance_actor;
+pub mod scheduler_actor;
+pub mod renderer_actor;
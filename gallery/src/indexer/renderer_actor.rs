@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+use std::collections::HashMap;
+
+use actix_web::actix::{
+    Actor, ActorFuture, Addr, Arbiter, AsyncContext, Context, Handler, Message, ResponseFuture,
+    SyncArbiter, SyncContext, WrapFuture,
+};
+use actix_web::actix::fut;
+use futures::future::Future;
+use futures::sync::oneshot;
+
+use crate::models::photo_thumbnail::PhotoThumbnail;
+use crate::models::source_format::SourceFormat;
+use crate::config::{Config, ThumbnailConfig};
+use crate::error::GalleryError;
+
+/// Generates the thumbnails requested by the browsers, sending the work to a
+/// fixed number of workers. Requests for a thumbnail already being generated
+/// wait for the same work to complete.
+pub struct RendererActor {
+    workers: Addr<RenderWorker>,
+    config: Config,
+    pending: HashMap<PathBuf, Vec<oneshot::Sender<Result<(), String>>>>,
+}
+
+impl Actor for RendererActor {
+    type Context = Context<Self>;
+}
+
+impl RendererActor {
+    pub fn init(config: Config) -> Addr<Self> {
+        let workers_config = config.clone();
+        let workers = SyncArbiter::start(config.render.workers.max(1), move || {
+            RenderWorker {
+                config: workers_config.clone(),
+            }
+        });
+
+        Arbiter::start(move |_ctx| {
+            RendererActor {
+                workers,
+                config,
+                pending: HashMap::new(),
+            }
+        })
+    }
+}
+
+pub struct RenderWorker {
+    config: Config,
+}
+
+impl Actor for RenderWorker {
+    type Context = SyncContext<Self>;
+}
+
+pub struct RenderThumbnail {
+    /// Path of the source file.
+    pub path: PathBuf,
+    pub hash: String,
+    pub orientation: Option<i32>,
+    pub thumbnail_config: ThumbnailConfig,
+}
+
+impl Message for RenderThumbnail {
+    type Result = Result<PathBuf, GalleryError>;
+}
+
+impl Handler<RenderThumbnail> for RendererActor {
+    type Result = ResponseFuture<PathBuf, GalleryError>;
+
+    fn handle(&mut self, msg: RenderThumbnail, ctx: &mut Self::Context) -> Self::Result {
+        let thumbnail_path = PhotoThumbnail::get_image_path(&msg.hash, &msg.thumbnail_config, self.config.cache_path.clone());
+
+        let (sender, receiver) = oneshot::channel();
+        let waiters = self.pending.entry(thumbnail_path.clone()).or_default();
+        waiters.push(sender);
+
+        if waiters.len() == 1 {
+            let key = thumbnail_path.clone();
+            let render = self.workers.send(msg)
+                .into_actor(self)
+                .then(move |res, act, _ctx| {
+                    let res = match res {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(err)) => Err(err.to_string()),
+                        Err(err) => Err(err.to_string()),
+                    };
+                    if let Err(ref err) = res {
+                        error!("Could not render {:?}, {}", key, err);
+                    }
+
+                    for waiter in act.pending.remove(&key).unwrap_or_default() {
+                        let _ = waiter.send(res.clone());
+                    }
+                    fut::ok(())
+                });
+            ctx.spawn(render);
+        } else {
+            debug!("Waiting for {:?} being rendered.", thumbnail_path);
+        }
+
+        Box::new(receiver.then(move |res| match res {
+            Ok(Ok(())) => Ok(thumbnail_path),
+            Ok(Err(err)) => Err(GalleryError::RenderError(err)),
+            Err(_) => Err(GalleryError::RenderError("renderer stopped".to_string())),
+        }))
+    }
+}
+
+impl Handler<RenderThumbnail> for RenderWorker {
+    type Result = Result<PathBuf, GalleryError>;
+
+    fn handle(&mut self, msg: RenderThumbnail, _ctx: &mut Self::Context) -> Self::Result {
+        debug!("Rendering thumbnail of {:?}", msg.path);
+        let format = SourceFormat::detect(&msg.path)?.ok_or(GalleryError::UnsupportedFormat)?;
+        let img = format.decode(&msg.path, msg.orientation, &self.config.decoders)?;
+
+        PhotoThumbnail::create_image(&img, &msg.hash, &msg.thumbnail_config, &self.config.encoders, self.config.cache_path.clone())
+    }
+}
@@ -0,0 +1,99 @@
+use std::str::FromStr;
+
+use actix_web::actix::{Actor, Addr, Arbiter, AsyncContext, Context, Handler, Message};
+use chrono::Local;
+use cron::Schedule;
+use futures::future::Future;
+
+use crate::models::job::CreateJob;
+use crate::models::db::DbExecutor;
+use crate::config::ScheduledJobConfig;
+use crate::common::JobRunners;
+use crate::error::GalleryError;
+
+
+/// Creates the jobs configured to run periodically.
+pub struct SchedulerActor {
+    db: Addr<DbExecutor>,
+    runners: JobRunners,
+    entries: Vec<(String, Schedule)>,
+}
+
+impl Actor for SchedulerActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        for index in 0..self.entries.len() {
+            self.schedule_next(index, ctx);
+        }
+    }
+}
+
+impl SchedulerActor {
+    pub fn init(db: Addr<DbExecutor>, runners: JobRunners, scheduled_jobs: Vec<ScheduledJobConfig>) -> Addr<Self> {
+        let entries = scheduled_jobs.into_iter()
+            .filter_map(|scheduled_job| {
+                if !JobRunners::KINDS.contains(&scheduled_job.name.as_str()) {
+                    error!("Unknown kind of job {:?} in schedule, ignoring it.", scheduled_job.name);
+                    return None;
+                }
+                match Schedule::from_str(&scheduled_job.schedule) {
+                    Ok(schedule) => Some((scheduled_job.name, schedule)),
+                    Err(err) => {
+                        error!("Invalid schedule {:?} for job {:?}, {}", scheduled_job.schedule, scheduled_job.name, err);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Arbiter::start(move |_ctx| {
+            SchedulerActor {
+                db,
+                runners,
+                entries,
+            }
+        })
+    }
+
+    fn schedule_next(&self, index: usize, ctx: &mut Context<Self>) {
+        let (ref name, ref schedule) = self.entries[index];
+
+        let next_run = match schedule.upcoming(Local).next() {
+            Some(next_run) => next_run,
+            None => return,
+        };
+        info!("Next {} job scheduled at {}.", name, next_run);
+
+        let delay = (next_run - Local::now()).to_std().unwrap_or_default();
+        ctx.notify_later(RunScheduledJob { index }, delay);
+    }
+}
+
+struct RunScheduledJob {
+    index: usize,
+}
+
+impl Message for RunScheduledJob {
+    type Result = ();
+}
+
+impl Handler<RunScheduledJob> for SchedulerActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: RunScheduledJob, ctx: &mut Self::Context) -> Self::Result {
+        let name = self.entries[msg.index].0.clone();
+
+        match self.db.send(CreateJob { name: name.clone() }).wait() {
+            Ok(Ok(job_id)) => {
+                info!("Starting scheduled {} job {}.", name, job_id);
+                self.runners.start(&name, job_id);
+            },
+            Ok(Err(GalleryError::JobAlreadyRunning)) => warn!("Scheduled {} job skipped, one is already running.", name),
+            Ok(Err(err)) => error!("Could not create scheduled {} job, {}", name, err),
+            Err(err) => error!("Could not create scheduled {} job, {}", name, err),
+        }
+
+        self.schedule_next(msg.index, ctx);
+    }
+}
@@ -1,11 +1,13 @@
 use std::fs;
 use std::path::PathBuf;
+use std::collections::HashSet;
 
 use actix_web::actix::{Actor, Addr, Arbiter, Context, Handler, Message};
 use futures::future::{Future, join_all};
 
-use crate::models::album::{CreateAlbum, GetAlbumId, GetRootAlbumId};
-use crate::models::job::{ChangeState, self};
+use crate::models::album::{CreateAlbum, GetAlbumId, GetRootAlbumId, DeleteStaleAlbums, RESERVED_ROOT_NAMES};
+use crate::models::photo::{GetAlbumPhotoNames, DeleteStalePhotos, StackPhotos};
+use crate::models::job::{Job, ChangeState, UpdateProgress, LogError, JobProgress, self};
 use crate::models::db::DbExecutor;
 use crate::config::Config;
 use crate::error::GalleryError;
@@ -13,6 +15,35 @@ use crate::utils;
 use crate::indexer::indexer_actor::{IndexerActor, IndexFile};
 
 
+/// Albums and photos encountered during a walk, anything else in the
+/// database no longer exists in storage once the walk is complete.
+struct IndexState {
+    job_id: String,
+    root_album_id: String,
+    progress: JobProgress,
+    seen_albums: HashSet<String>,
+    seen_photos: HashSet<String>,
+}
+
+impl IndexState {
+    fn new(job_id: String, root_album_id: String) -> Self {
+        IndexState {
+            job_id,
+            root_album_id,
+            progress: JobProgress::default(),
+            seen_albums: HashSet::new(),
+            seen_photos: HashSet::new(),
+        }
+    }
+
+    fn report_progress(&self, db: &Addr<DbExecutor>) {
+        db.do_send(UpdateProgress {
+            job_id: self.job_id.clone(),
+            progress: self.progress.clone(),
+        });
+    }
+}
+
 pub struct WalkerActor {
     db: Addr<DbExecutor>,
     indexer: Addr<IndexerActor>,
@@ -33,10 +64,20 @@ impl WalkerActor {
             }
         })
     }
-    fn index_children(&self, path: PathBuf, parent: String) -> Result<(), GalleryError> {
+    fn index_children(&self, path: PathBuf, parent: String, state: &mut IndexState) -> Result<(), GalleryError> {
         let mut children_future = Vec::new();
+        let mut files = Vec::new();
         let mut directories = Vec::new();
 
+        Job::check_cancelled(&state.job_id, &self.db)?;
+
+        state.progress.current_directory = Some(path.to_string_lossy().to_string());
+        state.report_progress(&self.db);
+
+        let known_photos = self.db.send(GetAlbumPhotoNames {
+            album_id: parent.clone(),
+        }).wait()??;
+
         for entry in fs::read_dir(path)? {
             let entry = entry?;
             let path = entry.path();
@@ -44,24 +85,62 @@ impl WalkerActor {
                 directories.push(path);
             } else if path.is_file() {
                 children_future.push(self.indexer.send(IndexFile {
-                    path,
+                    path: path.clone(),
                     parent: parent.clone(),
                 }));
+                files.push(path);
             }
         }
-        join_all(children_future).wait()?.iter()
-            .filter_map(|r| r.as_ref().err())
-            .for_each(|err| error!("Error during indexing, {}", err));
+
+        let mut directory_photos = Vec::new();
+        let results = join_all(children_future).wait()?;
+        for (path, result) in files.iter().zip(results) {
+            state.progress.files_seen += 1;
+            match result {
+                Ok(indexed_file) => {
+                    if indexed_file.updated {
+                        state.progress.files_indexed += 1;
+                    } else {
+                        state.progress.files_skipped += 1;
+                    }
+                    directory_photos.push(indexed_file.photo_id);
+                },
+                Err(GalleryError::InvalidFileName) | Err(GalleryError::UnsupportedFormat) => state.progress.files_skipped += 1,
+                Err(err) => {
+                    error!("Error during indexing, {}", err);
+                    state.progress.files_failed += 1;
+                    self.db.do_send(LogError {
+                        job_id: state.job_id.clone(),
+                        path: Some(path.to_string_lossy().to_string()),
+                        message: err.to_string(),
+                    });
+                    // The file is still there, keep what we already know about it.
+                    let known_id = utils::get_file_name_string(path).ok()
+                        .and_then(|name| known_photos.get(&name));
+                    if let Some(photo_id) = known_id {
+                        directory_photos.push(photo_id.clone());
+                    }
+                }
+            }
+        }
+
+        state.seen_photos.extend(directory_photos.iter().cloned());
+        self.db.send(StackPhotos { photo_ids: directory_photos }).wait()??;
+
         for dir in directories {
-            self.index_directory(dir, parent.clone())?;
+            self.index_directory(dir, parent.clone(), state)?;
         }
         Ok(())
     }
 
-    fn index_directory(&self, path: PathBuf, parent: String) -> Result<(), GalleryError> {
+    fn index_directory(&self, path: PathBuf, parent: String, state: &mut IndexState) -> Result<(), GalleryError> {
         info!("Indexing directory {:?}", path);
 
         let name =  utils::get_file_name_string(&path)?;
+        if parent == state.root_album_id && RESERVED_ROOT_NAMES.contains(&name.as_str()) {
+            warn!("Directory {:?} skipped, its name is reserved for the pages of the gallery.", path);
+            return Ok(());
+        }
 
         let album_id_opt = self.db.send(GetAlbumId {
             name: name.clone(),
@@ -72,8 +151,46 @@ impl WalkerActor {
             Some(id) => id,
             None => self.create_album(name, Some(parent))?
         };
+        state.seen_albums.insert(album_id.clone());
+
+        self.index_children(path, album_id, state)?;
+        Ok(())
+    }
+
+    fn index_gallery(&self, job_id: String) -> Result<(), GalleryError> {
+        Job::check_cancelled(&job_id, &self.db)?;
+
+        self.db.send(ChangeState {
+            job_id: job_id.clone(),
+            new_state: job::STATE_RUNNING.to_string(),
+        }).wait()??;
+
+        info!("Starting building index.");
+
+        let storage_path = fs::canonicalize(&self.config.storage_path)?;
+
+        let root_id_opt = self.db.send(GetRootAlbumId).wait()??;
+
+        let root_id = match root_id_opt {
+            Some(id) => id,
+            None => self.create_album(self.config.gallery_name.clone(), None)?
+        };
+
+        let mut state = IndexState::new(job_id.clone(), root_id.clone());
+        state.seen_albums.insert(root_id.clone());
+
+        self.index_children(storage_path, root_id, &mut state)?;
+        state.report_progress(&self.db);
+
+        let removed_photos = self.db.send(DeleteStalePhotos {
+            seen_photos: state.seen_photos,
+        }).wait()??;
+        let removed_albums = self.db.send(DeleteStaleAlbums {
+            seen_albums: state.seen_albums,
+        }).wait()??;
+        info!("Removed {} photos and {} albums no longer in storage.", removed_photos, removed_albums);
+        info!("Done!");
 
-        self.index_children(path, album_id)?;
         Ok(())
     }
 
@@ -98,30 +215,7 @@ impl Handler<StartWalking> for WalkerActor {
     type Result = Result<(), GalleryError>;
 
     fn handle(&mut self, msg: StartWalking, _ctx: &mut Self::Context) -> Self::Result {
-        self.db.send(ChangeState {
-            job_id: msg.job_id.clone(),
-            new_state: job::STATE_RUNNING.to_string(),
-        }).wait()??;
-
-        info!("Starting building index.");
-
-        let storage_path = fs::canonicalize(&self.config.storage_path)?;
-
-        let root_id_opt = self.db.send(GetRootAlbumId).wait()??;
-
-        let root_id = match root_id_opt {
-            Some(id) => id,
-            None => self.create_album(self.config.gallery_name.clone(), None)?
-        };
-
-        self.index_children(storage_path, root_id)?;
-        info!("Done!");
-
-        self.db.send(ChangeState {
-            job_id: msg.job_id,
-            new_state: job::STATE_FINISHED.to_string(),
-        }).wait()??;
-
-        Ok(())
+        let result = self.index_gallery(msg.job_id.clone());
+        Job::report_outcome(msg.job_id, result, &self.db)
     }
 }
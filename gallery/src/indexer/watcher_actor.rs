@@ -0,0 +1,335 @@
+use std::fs;
+use std::thread;
+use std::time::Duration;
+use std::sync::mpsc::channel;
+use std::path::{Path, PathBuf};
+
+use actix_web::actix::{Actor, ActorContext, Addr, Arbiter, AsyncContext, Context, Handler, Message};
+use futures::future::{Future, join_all};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::models::album::{CreateAlbum, GetAlbumId, GetRootAlbumId, DeleteAlbum, RESERVED_ROOT_NAMES};
+use crate::models::photo::{FindPhoto, DeletePhoto, GetAlbumPhotoNames, StackPhotos};
+use crate::models::job::{CreateJob, ChangeState, FailJob, UpdateProgress, LogError, JobProgress, self};
+use crate::models::db::DbExecutor;
+use crate::config::Config;
+use crate::error::{GalleryError, GalleryInternalError};
+use crate::utils;
+use crate::indexer::indexer_actor::{IndexerActor, IndexFile, IndexedFile};
+use crate::indexer::walker_actor::{WalkerActor, StartWalking};
+
+
+/// Keeps the index up to date by listening to filesystem events in the
+/// storage directory. Its lifetime is tracked as a long running job.
+pub struct WatcherActor {
+    db: Addr<DbExecutor>,
+    indexer: Addr<IndexerActor>,
+    walker: Addr<WalkerActor>,
+    config: Config,
+    storage_path: PathBuf,
+    job_id: Option<String>,
+    progress: JobProgress,
+    watcher: Option<RecommendedWatcher>,
+}
+
+impl Actor for WatcherActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Err(err) = self.start_watching(ctx.address()) {
+            error!("Could not watch storage directory, {}", err);
+            if let Some(job_id) = self.job_id.take() {
+                self.db.do_send(FailJob {
+                    job_id,
+                    reason: err.to_string(),
+                });
+            }
+            ctx.stop();
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.watcher = None;
+        if let Some(job_id) = self.job_id.take() {
+            self.db.do_send(ChangeState {
+                job_id,
+                new_state: job::STATE_FINISHED.to_string(),
+            });
+        }
+    }
+}
+
+impl WatcherActor {
+    pub fn init(db: Addr<DbExecutor>, indexer: Addr<IndexerActor>, walker: Addr<WalkerActor>, config: Config)
+        -> Addr<Self>
+    {
+        Arbiter::start(move |_ctx| {
+            WatcherActor {
+                db,
+                indexer,
+                walker,
+                config,
+                storage_path: PathBuf::new(),
+                job_id: None,
+                progress: JobProgress::default(),
+                watcher: None,
+            }
+        })
+    }
+
+    fn start_watching(&mut self, addr: Addr<Self>) -> Result<(), GalleryError> {
+        let job_id = self.db.send(CreateJob {
+            name: job::JOB_WATCH_GALLERY.to_string(),
+        }).wait()??;
+
+        self.db.send(ChangeState {
+            job_id: job_id.clone(),
+            new_state: job::STATE_RUNNING.to_string(),
+        }).wait()??;
+        self.job_id = Some(job_id);
+
+        self.storage_path = fs::canonicalize(&self.config.storage_path)?;
+
+        let (tx, rx) = channel();
+        let delay = Duration::from_secs(self.config.watcher.debounce_delay);
+        let mut watcher: RecommendedWatcher = Watcher::new(tx, delay).map_err(GalleryInternalError)?;
+        watcher.watch(&self.storage_path, RecursiveMode::Recursive).map_err(GalleryInternalError)?;
+        self.watcher = Some(watcher);
+
+        // The receiving end is blocking, forward the events from a dedicated
+        // thread. It ends by itself when the watcher is dropped.
+        thread::spawn(move || {
+            for event in rx {
+                addr.do_send(WatchEvent { event });
+            }
+        });
+
+        info!("Watching {:?} for changes.", self.storage_path);
+        Ok(())
+    }
+
+    fn index_path(&mut self, path: &Path) -> Result<(), GalleryError> {
+        if !path.starts_with(&self.storage_path) {
+            return Ok(());
+        }
+        let relative_path = path.strip_prefix(&self.storage_path)?;
+
+        // Only the directories at the root are albums, the files there being
+        // photos.
+        let root_name = relative_path.iter().next().and_then(|name| name.to_str()).unwrap_or_default();
+        let in_directory = path.is_dir() || relative_path.iter().nth(1).is_some();
+        if in_directory && RESERVED_ROOT_NAMES.contains(&root_name) {
+            warn!("{:?} skipped, the name of its directory is reserved for the pages of the gallery.", path);
+            return Ok(());
+        }
+
+        if path.is_dir() {
+            let album_id = self.get_or_create_album(relative_path)?;
+            self.index_directory(path, album_id)
+        } else if path.is_file() {
+            let album_id = self.get_or_create_album(relative_path.parent().unwrap_or_else(|| Path::new("")))?;
+            let result = self.indexer.send(IndexFile {
+                path: path.to_path_buf(),
+                parent: album_id.clone(),
+            }).wait()?;
+            self.record_result(path, result);
+            self.stack_album(album_id)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn index_directory(&mut self, path: &Path, album_id: String) -> Result<(), GalleryError> {
+        info!("Indexing new directory {:?}", path);
+        let mut children_future = Vec::new();
+        let mut files = Vec::new();
+        let mut directories = Vec::new();
+
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                directories.push(path);
+            } else if path.is_file() {
+                children_future.push(self.indexer.send(IndexFile {
+                    path: path.clone(),
+                    parent: album_id.clone(),
+                }));
+                files.push(path);
+            }
+        }
+
+        let results = join_all(children_future).wait()?;
+        for (path, result) in files.iter().zip(results) {
+            self.record_result(path, result);
+        }
+        self.stack_album(album_id.clone())?;
+
+        for dir in directories {
+            let child_id = self.get_or_create_child_album(utils::get_file_name_string(&dir)?, album_id.clone())?;
+            self.index_directory(&dir, child_id)?;
+        }
+        Ok(())
+    }
+
+    fn record_result(&mut self, path: &Path, result: Result<IndexedFile, GalleryError>) {
+        self.progress.files_seen += 1;
+        match result {
+            Ok(IndexedFile { updated: true, .. }) => self.progress.files_indexed += 1,
+            Ok(_) | Err(GalleryError::InvalidFileName) | Err(GalleryError::UnsupportedFormat) => self.progress.files_skipped += 1,
+            Err(err) => {
+                error!("Error during indexing, {}", err);
+                self.progress.files_failed += 1;
+                if let Some(ref job_id) = self.job_id {
+                    self.db.do_send(LogError {
+                        job_id: job_id.clone(),
+                        path: Some(path.to_string_lossy().to_string()),
+                        message: err.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Groups again the photos of the album once its content changed.
+    fn stack_album(&self, album_id: String) -> Result<(), GalleryError> {
+        let photo_ids = self.db.send(GetAlbumPhotoNames { album_id }).wait()??
+            .values()
+            .cloned()
+            .collect();
+        self.db.send(StackPhotos { photo_ids }).wait()?
+    }
+
+    fn remove_path(&self, path: &Path) -> Result<(), GalleryError> {
+        if !path.starts_with(&self.storage_path) || path.exists() {
+            return Ok(());
+        }
+        let relative_path = path.strip_prefix(&self.storage_path)?;
+        let name = utils::get_file_name_string(relative_path)?;
+
+        let parent_id = match self.find_album(relative_path.parent().unwrap_or_else(|| Path::new("")))? {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let photo = self.db.send(FindPhoto {
+            name: name.clone(),
+            album_id: parent_id.clone(),
+        }).wait()??;
+
+        if let Some(photo) = photo {
+            info!("Removing photo {:?}", path);
+            self.db.send(DeletePhoto { photo_id: photo.id }).wait()??;
+            return self.stack_album(parent_id);
+        }
+
+        let album_id = self.db.send(GetAlbumId {
+            name,
+            parent_album_id: parent_id,
+        }).wait()??;
+
+        if let Some(album_id) = album_id {
+            info!("Removing album {:?}", path);
+            self.db.send(DeleteAlbum { album_id }).wait()??;
+        }
+        Ok(())
+    }
+
+    fn rescan(&self) -> Result<(), GalleryError> {
+        warn!("Some filesystem events were missed, starting a full index.");
+        let job_id = self.db.send(CreateJob {
+            name: job::JOB_INDEX_GALLERY.to_string(),
+        }).wait()??;
+        self.walker.do_send(StartWalking { job_id });
+        Ok(())
+    }
+
+    fn find_album(&self, relative_path: &Path) -> Result<Option<String>, GalleryError> {
+        let mut album_id = match self.db.send(GetRootAlbumId).wait()?? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        for segment in relative_path.iter() {
+            album_id = match self.db.send(GetAlbumId {
+                name: segment.to_string_lossy().to_string(),
+                parent_album_id: album_id,
+            }).wait()?? {
+                Some(id) => id,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(album_id))
+    }
+
+    fn get_or_create_album(&self, relative_path: &Path) -> Result<String, GalleryError> {
+        let mut album_id = match self.db.send(GetRootAlbumId).wait()?? {
+            Some(id) => id,
+            None => self.db.send(CreateAlbum {
+                name: self.config.gallery_name.clone(),
+                parent_album_id: None,
+            }).wait()??,
+        };
+
+        for segment in relative_path.iter() {
+            album_id = self.get_or_create_child_album(segment.to_string_lossy().to_string(), album_id)?;
+        }
+        Ok(album_id)
+    }
+
+    fn get_or_create_child_album(&self, name: String, parent_album_id: String) -> Result<String, GalleryError> {
+        let album_id = self.db.send(GetAlbumId {
+            name: name.clone(),
+            parent_album_id: parent_album_id.clone(),
+        }).wait()??;
+
+        match album_id {
+            Some(id) => Ok(id),
+            None => self.db.send(CreateAlbum {
+                name,
+                parent_album_id: Some(parent_album_id),
+            }).wait()?,
+        }
+    }
+}
+
+pub struct WatchEvent {
+    pub event: DebouncedEvent,
+}
+
+impl Message for WatchEvent {
+    type Result = ();
+}
+
+impl Handler<WatchEvent> for WatcherActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: WatchEvent, _ctx: &mut Self::Context) -> Self::Result {
+        debug!("Filesystem event {:?}", msg.event);
+
+        let result = match msg.event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => self.index_path(&path),
+            DebouncedEvent::Remove(path) => self.remove_path(&path),
+            // Index the destination first so a moved photo gets relocated
+            // instead of being removed and processed again.
+            DebouncedEvent::Rename(from, to) => self.index_path(&to).and_then(|_| self.remove_path(&from)),
+            DebouncedEvent::Rescan => self.rescan(),
+            DebouncedEvent::Error(err, path) => {
+                error!("Error watching {:?}, {}", path, err);
+                Ok(())
+            },
+            _ => Ok(()),
+        };
+
+        match result {
+            Ok(()) | Err(GalleryError::InvalidFileName) => (),
+            Err(err) => error!("Error handling filesystem event, {}", err),
+        }
+
+        if let Some(ref job_id) = self.job_id {
+            self.db.do_send(UpdateProgress {
+                job_id: job_id.clone(),
+                progress: self.progress.clone(),
+            });
+        }
+    }
+}
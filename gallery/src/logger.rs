@@ -0,0 +1,26 @@
+use std::time::Instant;
+
+use actix_web::{HttpRequest, HttpResponse, Result};
+use actix_web::middleware::{Middleware, Started, Finished};
+
+/// Logs the requests like the Logger of actix-web, but without their query
+/// string, which can hold the tokens of the share links.
+pub struct RequestLogger;
+
+struct RequestStart(Instant);
+
+impl<S> Middleware<S> for RequestLogger {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        req.extensions_mut().insert(RequestStart(Instant::now()));
+        Ok(Started::Done)
+    }
+
+    fn finish(&self, req: &HttpRequest<S>, resp: &HttpResponse) -> Finished {
+        if let Some(start) = req.extensions().get::<RequestStart>() {
+            let elapsed = start.0.elapsed();
+            let millis = elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) / 1_000_000.0;
+            info!("\"{} {} {:?}\" {:.6}ms {}", req.method(), req.path(), req.version(), millis, resp.status().as_u16());
+        }
+        Finished::Done
+    }
+}
@@ -2,12 +2,14 @@
 #[macro_use] extern crate diesel;
 #[macro_use] extern crate serde;
 
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 
 use env_logger;
-use actix_web::middleware::Logger;
 use actix_web::{server, App, http::{NormalizePath, Method}, fs};
-use actix_web::actix::System;
+use actix_web::actix::{Arbiter, System, Handler, Message};
+use futures::future::Future;
 
 mod models;
 #[macro_use]
@@ -18,42 +20,189 @@ mod error;
 mod common;
 mod indexer;
 mod handlers;
+mod auth;
+mod logger;
+mod api;
 
 use config::Config;
-use common::AppState;
+use common::{AppState, JobRunners};
 
 fn create_app(app_state: AppState) -> App<AppState> {
     let static_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("static");
+    let thumbnail_sizes: Vec<String> = app_state.config.thumbnails.keys().cloned().collect();
+    let thumbnail_path = format!("/{{path:.*}}/{{thumbnail_size:{}}}", thumbnail_sizes.join("|"));
     App::with_state(app_state)
-        .middleware(Logger::new("\"%r\" %Dms %s"))
+        .middleware(logger::RequestLogger)
+        .middleware(auth::CsrfCookie)
         .scope("/admin", |admin_scope| {
             admin_scope
+                .middleware(auth::AdminAuth)
+                .resource("/login", |r| {
+                    r.method(Method::GET).with(routes::get_login_route);
+                    r.method(Method::POST).with_async(routes::post_login_route)
+                })
+                .resource("/logout", |r| r.method(Method::POST).with_async(routes::logout_route))
                 .resource("/jobs", |r| {
                     r.method(Method::GET).with_async(routes::get_jobs_route);
                     r.method(Method::POST).with_async(routes::post_jobs_route)
                 })
+                .resource("/jobs/{job_id}", |r| r.method(Method::GET).with_async(routes::get_job_route))
+                .resource("/jobs/{job_id}/cancel", |r| r.method(Method::POST).with_async(routes::cancel_job_route))
+                .resource("/shares", |r| {
+                    r.method(Method::GET).with_async(routes::get_shares_route);
+                    r.method(Method::POST).with_async(routes::post_shares_route)
+                })
+                .resource("/shares/{share_id}/revoke", |r| r.method(Method::POST).with_async(routes::revoke_share_route))
         })
         .handler("/static", fs::StaticFiles::new(static_path).unwrap())
-        .resource("/{path:.*}/{thumbnail_size:small|medium}", |r| r.with_async(routes::thumbnail_route))
-        .resource("/{path:.*}/full", |r| r.f(routes::full_photo_route))
-        .resource("/{path:.*}", |r| r.with_async(routes::gallery_route))
+        .scope("/api/v1", |api_scope| {
+            api_scope
+                .resource("/albums", |r| r.method(Method::GET).with_async(api::get_album_route))
+                .resource("/albums/{path:.*}", |r| r.method(Method::GET).with_async(api::get_album_route))
+                .resource("/photos/{path:.*}", |r| r.method(Method::GET).with_async(api::get_photo_route))
+                .resource("/search", |r| r.method(Method::GET).with_async(api::search_route))
+                .resource("/jobs", |r| {
+                    r.method(Method::GET).with_async(api::get_jobs_route);
+                    r.method(Method::POST).with_async(api::post_jobs_route)
+                })
+                .resource("/jobs/{job_id}", |r| r.method(Method::GET).with_async(api::get_job_route))
+                .default_resource(|r| r.f(api::not_found_route))
+        })
+        .resource("/search", |r| r.method(Method::GET).with_async(routes::search_route))
+        .resource("/timeline", |r| r.method(Method::GET).with_async(routes::timeline_route))
+        .resource("/timeline/{year}/{month}/{day}", |r| r.method(Method::GET).with_async(routes::timeline_day_route))
+        .resource(&thumbnail_path, |r| r.with_async(routes::thumbnail_route))
+        .resource("/{path:.*}/full", |r| r.with_async(routes::full_photo_route))
+        .resource("/{path:.*}", |r| {
+            r.method(Method::POST).with_async(routes::unlock_album_route);
+            r.with_async(routes::gallery_route)
+        })
         .default_resource(|r| r.h(NormalizePath::default()))
 }
 
+const USAGE: &str = "Usage: gallery [set-password <username> [<group>...] | set-access <album path> <access> [<user or @group>...] \
+                     | set-sort <album path> <sort>]";
+
+fn read_password() -> String {
+    let mut password = String::new();
+    io::stdin().read_line(&mut password).expect("Could not read the password");
+    let password = password.trim_end_matches(&['\n', '\r'][..]).to_string();
+    if password.is_empty() {
+        error!("The password can't be empty.");
+        process::exit(1);
+    }
+    password
+}
+
+/// Runs a command of the command line on the database, then exits.
+fn run_command<M>(config: &Config, msg: M, description: String)
+where
+    M: Message<Result = Result<(), error::GalleryError>> + Send + 'static,
+    models::db::DbExecutor: Handler<M>,
+{
+    let sys = System::new("gallery");
+    let db_addr = models::db::init(config.db.url.clone());
+    Arbiter::spawn(db_addr.send(msg)
+        .from_err::<error::GalleryError>()
+        .flatten()
+        .then(move |res| {
+            if let Err(err) = res {
+                error!("Could not {}, {}", description, err);
+                process::exit(1);
+            }
+            System::current().stop();
+            Ok(())
+        }));
+    let _ = sys.run();
+}
+
 fn main() {
     std::env::set_var("RUST_LOG", "actix_web=debug,gallery=debug");
     env_logger::init();
-    let sys = System::new("gallery");
 
     let config = Config::load();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => (),
+        // The password is read from the standard input, groups are kept when
+        // none are given.
+        ["set-password", username, groups @ ..] => {
+            let set_password = models::user::SetPassword {
+                username: username.to_string(),
+                password: read_password(),
+                groups: if groups.is_empty() { None } else { Some(groups.iter().map(|group| group.to_string()).collect()) },
+            };
+            return run_command(&config, set_password, format!("set the password of {}", username));
+        },
+        ["set-access", album_path, access, principals @ ..] if models::album::ACCESSES.contains(access) => {
+            let set_access = models::album::SetAlbumAccess {
+                path: PathBuf::from(album_path),
+                access: access.to_string(),
+                password: if *access == models::album::ACCESS_PASSWORD { Some(read_password()) } else { None },
+                principals: principals.iter().map(|principal| principal.to_string()).collect(),
+            };
+            return run_command(&config, set_access, format!("set the access to {}", album_path));
+        },
+        ["set-sort", album_path, sort] if models::photo_thumbnail::PhotoSort::from_param(sort).is_some() => {
+            let set_sort = models::album::SetAlbumSort {
+                path: PathBuf::from(album_path),
+                sort: models::photo_thumbnail::PhotoSort::from_param(sort).unwrap_or_default(),
+            };
+            return run_command(&config, set_sort, format!("set the order of {}", album_path));
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    }
+
+    let sys = System::new("gallery");
     let db_addr = models::db::init(config.db.url.clone());
     let index_addr = indexer::indexer_actor::IndexerActor::init(db_addr.clone(), config.clone());
     let walker_addr = indexer::walker_actor::WalkerActor::init(db_addr.clone(), index_addr.clone(), config.clone());
+    let maintenance_addr = indexer::maintenance_actor::MaintenanceActor::init(db_addr.clone(), index_addr.clone(), config.clone());
+
+    let job_runners = JobRunners {
+        walker: walker_addr.clone(),
+        maintenance: maintenance_addr,
+    };
+
+    // Jobs left running are recovered before new ones are created.
+    let (background_db_addr, background_runners, background_config) = (db_addr.clone(), job_runners.clone(), config.clone());
+    Arbiter::spawn(job_runners.recover(db_addr.clone(), config.jobs.resume_interrupted)
+        .then(move |res| {
+            if let Err(err) = res {
+                error!("Could not recover interrupted jobs, {}", err);
+            }
+
+            if !background_config.jobs.scheduled.is_empty() {
+                indexer::scheduler_actor::SchedulerActor::init(
+                    background_db_addr.clone(),
+                    background_runners,
+                    background_config.jobs.scheduled.clone()
+                );
+            }
+
+            if background_config.watcher.enabled {
+                indexer::watcher_actor::WatcherActor::init(background_db_addr, index_addr, walker_addr, background_config);
+            }
+            Ok(())
+        }));
+
+    let renderer_addr = if config.render.on_demand {
+        Some(indexer::renderer_actor::RendererActor::init(config.clone()))
+    } else {
+        None
+    };
 
     let app_state = AppState {
         config: config,
         db: db_addr,
-        walker: walker_addr
+        hasher: models::user::PasswordHasher::init(),
+        login_throttle: auth::LoginThrottle::default(),
+        jobs: job_runners,
+        renderer: renderer_addr,
     };
 
     server::new(move || create_app(app_state.clone()))
@@ -1,15 +1,30 @@
 use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
 
 use askama::Template;
 use actix_web::actix::{Addr, Message};
-use futures::future::Future;
+use futures::future::{self, Future};
+use sha2::{Sha256, Digest};
 
 use super::db::DbExecutor;
 use super::schema::albums;
 use super::album_thumbnail::{AlbumThumbnail, GetAlbumsThumbnail};
-use super::photo_thumbnail::{PhotoThumbnail, GetPhotosThumbnail};
+use super::photo_thumbnail::{PhotoThumbnail, PhotoSort, GetPhotosThumbnail, ThumbnailSizes};
+use super::user::{Identity, PasswordHasher, VerifyPassword};
 use crate::error::GalleryError;
 
+pub const ACCESS_PUBLIC: &str = "public";
+/// Only visible to the logged in users.
+pub const ACCESS_PRIVATE: &str = "private";
+pub const ACCESS_PASSWORD: &str = "password";
+/// Only visible to the users and groups granted access.
+pub const ACCESS_RESTRICTED: &str = "restricted";
+pub const ACCESSES: &[&str] = &[ACCESS_PUBLIC, ACCESS_PRIVATE, ACCESS_PASSWORD, ACCESS_RESTRICTED];
+
+/// Names of the pages of the gallery, which would hide the albums at the root
+/// named the same. Such directories are left out of the index.
+pub const RESERVED_ROOT_NAMES: &[&str] = &["admin", "api", "static", "search", "timeline"];
+
 #[derive(Debug, Template)]
 #[template(path = "album.html")]
 pub struct AlbumTemplate {
@@ -17,7 +32,42 @@ pub struct AlbumTemplate {
     pub breadcrumb: Vec<(String, String)>,
     pub album_path: String,
     pub albums: Vec<AlbumThumbnail>,
-    pub photos: Vec<PhotoThumbnail>
+    pub photos: Vec<PhotoThumbnail>,
+    pub thumbnail_sizes: ThumbnailSizes,
+    /// Keeps shared caches from storing the page.
+    pub is_restricted: bool,
+    pub sort: PhotoSort,
+    /// Query string keeping the order asked for in the links, empty when
+    /// the default one of the album is used.
+    pub sort_query: String,
+    pub pagination: Pagination,
+}
+
+/// Navigation between the pages of a listing.
+#[derive(Debug)]
+pub struct Pagination {
+    /// Starting at 1.
+    pub page: i64,
+    pub page_count: i64,
+    /// Query string of the listing kept in the links, without the page.
+    pub query: String,
+}
+
+pub struct PageLink {
+    pub page: i64,
+    pub url: String,
+    pub is_current: bool,
+}
+
+/// Page asking for the password of an album.
+#[derive(Template)]
+#[template(path = "unlock.html")]
+pub struct UnlockTemplate {
+    pub name: String,
+    pub breadcrumb: Vec<(String, String)>,
+    pub album_id: String,
+    pub csrf_token: String,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Insertable, Identifiable, Queryable, Associations)]
@@ -27,43 +77,182 @@ pub struct Album {
     pub id: String,
     pub name: String,
     pub parent_album_id: Option<String>,
+    /// Restriction of the album, also applying to its sub-albums.
+    pub access: String,
+    pub password_hash: Option<String>,
+    /// Default order of the photos, see `PhotoSort::as_param`.
+    pub sort: Option<String>,
 }
 
 pub struct AlbumResult {
     pub album: Album,
     pub breadcrumb: Vec<(String, String)>,
+    /// Whether the album or one of its parents is not public.
+    pub is_restricted: bool,
+    pub grant: AccessGrant,
+}
+
+/// Page of an album asked for by the viewer.
+#[derive(Debug, Clone, Copy)]
+pub struct PageRequest {
+    /// Order of the photos, the default one of the album when not set.
+    pub sort: Option<PhotoSort>,
+    /// Starting at 1.
+    pub page: i64,
+    pub per_page: i64,
+}
+
+/// Person browsing the gallery, whose access to the albums is checked.
+#[derive(Debug, Clone, Default)]
+pub struct Viewer {
+    pub identity: Option<Identity>,
+    /// Tokens of the password protected albums unlocked by the viewer.
+    pub unlock_tokens: Vec<String>,
+    /// Tokens of the share links followed by the viewer.
+    pub share_tokens: Vec<String>,
+}
+
+/// How the viewer was granted access to an album.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessGrant {
+    /// By the restrictions of the album and its parents.
+    Direct,
+    /// By a share link of the album or of one of its parents.
+    SharedAlbum { allow_download: bool },
+    /// By a share link of the photo requested, giving no access to the rest
+    /// of the album.
+    SharedPhoto { allow_download: bool },
+}
+
+impl AccessGrant {
+    pub fn allows_download(self) -> bool {
+        match self {
+            AccessGrant::Direct => true,
+            AccessGrant::SharedAlbum { allow_download } | AccessGrant::SharedPhoto { allow_download } => allow_download,
+        }
+    }
+}
+
+impl Viewer {
+    pub fn is_admin(&self) -> bool {
+        self.identity.as_ref().map(Identity::is_admin).unwrap_or(false)
+    }
+}
+
+impl PageRequest {
+    /// Reads the `sort` and `page` query parameters, ignoring invalid values.
+    pub fn from_query(query: &HashMap<String, String>, per_page: i64) -> Self {
+        PageRequest {
+            sort: query.get("sort").and_then(|sort| PhotoSort::from_param(sort)),
+            page: query.get("page").and_then(|page| page.parse().ok()).filter(|&page| page >= 1).unwrap_or(1),
+            per_page: per_page.max(1),
+        }
+    }
+}
+
+impl Pagination {
+    /// The page count is computed from the number of items listed.
+    pub fn new(page: i64, item_count: i64, per_page: i64, query: String) -> Self {
+        Pagination {
+            page,
+            page_count: ((item_count + per_page - 1) / per_page).max(1),
+            query,
+        }
+    }
+
+    /// Link to another page of the listing.
+    pub fn get_page_url(&self, page: i64) -> String {
+        match self.query.as_str() {
+            "" => format!("?page={}", page),
+            query => format!("{}&page={}", query, page),
+        }
+    }
+
+    pub fn get_previous_page_url(&self) -> Option<String> {
+        if self.page > 1 { Some(self.get_page_url(self.page - 1)) } else { None }
+    }
+
+    pub fn get_next_page_url(&self) -> Option<String> {
+        if self.page < self.page_count { Some(self.get_page_url(self.page + 1)) } else { None }
+    }
+
+    /// Pages linked in the navigation, the first, the last and the ones
+    /// around the current page, None standing for the pages skipped.
+    pub fn get_page_links(&self) -> Vec<Option<PageLink>> {
+        let mut links = Vec::new();
+        for page in 1..=self.page_count {
+            if page == 1 || page == self.page_count || (page - self.page).abs() <= 2 {
+                links.push(Some(PageLink { page, url: self.get_page_url(page), is_current: page == self.page }));
+            } else if links.last().map(Option::is_some).unwrap_or(true) {
+                links.push(None);
+            }
+        }
+        links
+    }
 }
 
 impl AlbumTemplate {
-    pub fn get(path: PathBuf, db: Addr<DbExecutor>) -> impl Future<Item = Self, Error = GalleryError> {
-        Album::get(path.clone(), db.clone())
+    /// Sub-albums are only listed on the first page.
+    pub fn get(path: PathBuf, page_request: PageRequest, thumbnail_sizes: ThumbnailSizes, viewer: Viewer, db: Addr<DbExecutor>)
+        -> impl Future<Item = Self, Error = GalleryError>
+    {
+        Album::get(path.clone(), None, viewer.clone(), db.clone())
             .and_then(move |res| {
-                let albums_tn_future = db.send(GetAlbumsThumbnail {
-                    parent_album_id: res.album.id.clone()
-                });
+                let albums_tn_future: Box<Future<Item = Vec<AlbumThumbnail>, Error = GalleryError>> = if page_request.page == 1 {
+                    Box::new(db.send(GetAlbumsThumbnail {
+                        parent_album_id: res.album.id.clone(),
+                        viewer,
+                        grant: res.grant,
+                    }).from_err::<GalleryError>().flatten())
+                } else {
+                    Box::new(future::ok(Vec::new()))
+                };
+                let sort = page_request.sort.unwrap_or_else(|| res.album.get_sort());
                 let photos_tn_future = db.send(GetPhotosThumbnail {
-                    parent_album_id: res.album.id.clone()
-                });
+                    parent_album_id: res.album.id.clone(),
+                    sort,
+                    offset: (page_request.page - 1).saturating_mul(page_request.per_page),
+                    limit: page_request.per_page,
+                }).from_err::<GalleryError>().flatten();
                 albums_tn_future
                     .join3(photos_tn_future, Ok(res))
-                    .from_err()
-                    .and_then(move |(albums, photos, res)| {
-                        match (albums, photos) {
-                            (Ok(albums), Ok(photos)) => {
-                                Ok(AlbumTemplate {
-                                    name: res.album.name,
-                                    breadcrumb: res.breadcrumb,
-                                    album_path: Self::get_album_url(path),
-                                    albums: albums,
-                                    photos: photos,
-                                })
-                            },
-                            (Err(e), _) | (_, Err(e)) => Err(e)
+                    .and_then(move |(albums, (photos, photo_count), res)| {
+                        let sort_query = page_request.sort.map(PhotoSort::get_query).unwrap_or_default();
+                        let pagination = Pagination::new(page_request.page, photo_count, page_request.per_page, sort_query.clone());
+                        if pagination.page > pagination.page_count {
+                            return Err(GalleryError::NotFound);
                         }
+
+                        Ok(AlbumTemplate {
+                            name: res.album.name,
+                            breadcrumb: res.breadcrumb,
+                            album_path: Self::get_album_url(path),
+                            albums: albums,
+                            photos: photos,
+                            thumbnail_sizes,
+                            is_restricted: res.is_restricted,
+                            sort,
+                            sort_query,
+                            pagination,
+                        })
                     })
             })
     }
 
+    pub fn get_photo_src(&self, photo: &PhotoThumbnail) -> String {
+        let path = format!("{}/{}", self.album_path, photo.name);
+        self.thumbnail_sizes.get_default_url(&path, &photo.hash)
+    }
+
+    pub fn get_photo_srcset(&self, photo: &PhotoThumbnail) -> String {
+        let path = format!("{}/{}", self.album_path, photo.name);
+        self.thumbnail_sizes.get_srcset(&path, &photo.hash, photo.width, photo.height)
+    }
+
+    pub fn get_sorts(&self) -> &'static [PhotoSort] {
+        PhotoSort::ALL
+    }
+
     fn get_album_url(path: PathBuf) -> String {
         if path == PathBuf::from("") {
             "".to_string()
@@ -74,11 +263,49 @@ impl AlbumTemplate {
 }
 
 impl Album {
-    pub fn get(path: PathBuf, db: Addr<DbExecutor>) -> impl Future<Item = AlbumResult, Error = GalleryError> {
-        db.send(GetAlbum { path: path.clone() })
+    /// `photo_name` is the photo requested in the album, if any.
+    pub fn get(path: PathBuf, photo_name: Option<String>, viewer: Viewer, db: Addr<DbExecutor>)
+        -> impl Future<Item = AlbumResult, Error = GalleryError>
+    {
+        db.send(GetAlbum { path: path.clone(), viewer, photo_name })
             .from_err::<GalleryError>()
             .flatten()
     }
+
+    /// Checks that the viewer may see the album, its parents being checked
+    /// on their own. `grants` are the principals allowed in a restricted
+    /// album.
+    pub fn check_access(&self, viewer: &Viewer, grants: &[String]) -> Result<(), GalleryError> {
+        if viewer.is_admin() {
+            return Ok(());
+        }
+
+        match self.access.as_str() {
+            ACCESS_PUBLIC => Ok(()),
+            ACCESS_PRIVATE if viewer.identity.is_some() => Ok(()),
+            ACCESS_RESTRICTED if viewer.identity.as_ref().map(|identity| identity.is_granted(grants)).unwrap_or(false) => Ok(()),
+            ACCESS_PASSWORD => match self.get_unlock_token() {
+                Some(ref token) if viewer.unlock_tokens.contains(token) => Ok(()),
+                _ => Err(GalleryError::AlbumLocked { album_id: self.id.clone(), name: self.name.clone() }),
+            },
+            // Hiding the albums the viewer can't see, unknown restrictions
+            // included.
+            _ => Err(GalleryError::NotFound),
+        }
+    }
+
+    pub fn get_sort(&self) -> PhotoSort {
+        self.sort.as_ref()
+            .and_then(|sort| PhotoSort::from_param(sort))
+            .unwrap_or_default()
+    }
+
+    /// Token proving the password of the album was given, which changes with
+    /// the password.
+    pub fn get_unlock_token(&self) -> Option<String> {
+        self.password_hash.as_ref()
+            .map(|password_hash| format!("{:x}", Sha256::digest(format!("{}:{}", self.id, password_hash).as_bytes())))
+    }
 }
 
 pub struct CreateAlbum {
@@ -88,6 +315,10 @@ pub struct CreateAlbum {
 
 pub struct GetAlbum {
     pub path: PathBuf,
+    pub viewer: Viewer,
+    /// Photo requested in the album, which a share link may grant access to.
+    /// The last segment of the path is used when it is not an album.
+    pub photo_name: Option<String>,
 }
 
 pub struct GetAlbumId {
@@ -97,6 +328,65 @@ pub struct GetAlbumId {
 
 pub struct GetRootAlbumId;
 
+pub struct GetAlbumPath {
+    pub album_id: String,
+}
+
+/// Restricts the access to an album, given by its path in the storage.
+pub struct SetAlbumAccess {
+    pub path: PathBuf,
+    pub access: String,
+    pub password: Option<String>,
+    /// User names, or group names prefixed with "@".
+    pub principals: Vec<String>,
+}
+
+/// Sets the default order of the photos of an album, given by its path in
+/// the storage.
+pub struct SetAlbumSort {
+    pub path: PathBuf,
+    pub sort: PhotoSort,
+}
+
+pub struct UnlockAlbum {
+    pub album_id: String,
+    pub password: String,
+}
+
+impl UnlockAlbum {
+    /// Checks the password of the album, away from the database, returning
+    /// the token unlocking it.
+    pub fn unlock(self, db: Addr<DbExecutor>, hasher: Addr<PasswordHasher>) -> impl Future<Item = String, Error = GalleryError> {
+        let UnlockAlbum { album_id, password } = self;
+
+        db.send(GetLockedAlbum { album_id })
+            .from_err::<GalleryError>()
+            .flatten()
+            .and_then(move |album| {
+                let password_hash = if album.access == ACCESS_PASSWORD { album.password_hash.clone() } else { None };
+                hasher.send(VerifyPassword { password, password_hash })
+                    .from_err::<GalleryError>()
+                    .flatten()
+                    .and_then(move |verified| match album.get_unlock_token() {
+                        Some(token) if verified => Ok(token),
+                        _ => Err(GalleryError::AlbumLocked { album_id: album.id, name: album.name }),
+                    })
+            })
+    }
+}
+
+pub struct GetLockedAlbum {
+    pub album_id: String,
+}
+
+pub struct DeleteAlbum {
+    pub album_id: String,
+}
+
+pub struct DeleteStaleAlbums {
+    pub seen_albums: HashSet<String>,
+}
+
 impl Message for CreateAlbum {
     type Result = Result<String, GalleryError>;
 }
@@ -112,3 +402,27 @@ impl Message for GetAlbumId {
 impl Message for GetRootAlbumId {
     type Result = Result<Option<String>, GalleryError>;
 }
+
+impl Message for GetAlbumPath {
+    type Result = Result<PathBuf, GalleryError>;
+}
+
+impl Message for SetAlbumAccess {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for SetAlbumSort {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for GetLockedAlbum {
+    type Result = Result<Album, GalleryError>;
+}
+
+impl Message for DeleteAlbum {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for DeleteStaleAlbums {
+    type Result = Result<usize, GalleryError>;
+}
@@ -1,14 +1,21 @@
 use actix_web::actix::Message;
 
+use super::album::{Viewer, AccessGrant};
 use crate::error::GalleryError;
 
-#[derive(Debug, Queryable)]
+#[derive(Debug)]
 pub struct AlbumThumbnail {
-    pub name: String
+    pub name: String,
+    /// Protected by a password the viewer didn't give yet.
+    pub is_locked: bool,
 }
 
+/// Lists the sub-albums the viewer may see.
 pub struct GetAlbumsThumbnail {
     pub parent_album_id: String,
+    pub viewer: Viewer,
+    /// Sub-albums of a shared album are all visible.
+    pub grant: AccessGrant,
 }
 
 impl Message for GetAlbumsThumbnail {
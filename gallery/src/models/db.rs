@@ -3,6 +3,10 @@ use diesel;
 use diesel::prelude::*;
 use diesel::r2d2::{Pool, ConnectionManager};
 
+// SQLite refuses statements with more than 999 bound parameters, queries
+// filtering on arbitrary long id lists are split into chunks of this size.
+pub const MAX_BOUND_PARAMETERS: usize = 500;
+
 pub struct DbExecutor {
     pub conn: Pool<ConnectionManager<SqliteConnection>>,
 }
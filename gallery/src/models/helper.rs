@@ -1,26 +1,78 @@
 use std::io;
 use std::fs;
 use std::path::PathBuf;
-use std::collections::HashMap;
 
-pub trait ExifExtractor {
-    const TAG_LIST: &'static [exif::Tag];
+use chrono::{NaiveDate, NaiveDateTime};
 
-    fn extract_exif_map(path: &PathBuf) -> io::Result<HashMap<exif::Tag, String>> {
-        let mut tag_map = HashMap::new();
+/// Conversion of a raw EXIF value into a field of a metadata struct.
+pub trait FromExifValue: Sized {
+    fn from_exif_value(tag: exif::Tag, value: &exif::Value) -> Option<Self>;
+}
 
-        let file = fs::File::open(path)?;
-        let res_reader = exif::Reader::new(&mut io::BufReader::new(&file));
-        if res_reader.is_err() {
-            return Ok(tag_map)
+/// Keywords set by Windows, which the exif crate doesn't know by name.
+pub const TAG_XP_KEYWORDS: exif::Tag = exif::Tag(exif::Context::Tiff, 0x9c9e);
+
+impl FromExifValue for String {
+    fn from_exif_value(tag: exif::Tag, value: &exif::Value) -> Option<Self> {
+        match (tag, value) {
+            // Free text is kept as written rather than quoted and escaped,
+            // cameras often fill it with blanks.
+            (exif::Tag::ImageDescription, exif::Value::Ascii(lines)) => {
+                let text = lines.iter()
+                    .map(|line| String::from_utf8_lossy(line).trim().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                Some(text).filter(|text| !text.trim().is_empty())
+            },
+            // Null terminated UTF-16 text, the keywords being separated by
+            // semicolons.
+            (TAG_XP_KEYWORDS, exif::Value::Byte(bytes)) => {
+                let units: Vec<u16> = bytes.chunks(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair.get(1).cloned().unwrap_or(0)]))
+                    .collect();
+                let keywords: Vec<String> = String::from_utf16_lossy(&units)
+                    .split(&[';', '\0'][..])
+                    .map(str::trim)
+                    .filter(|keyword| !keyword.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                Some(keywords.join(", ")).filter(|keywords| !keywords.is_empty())
+            },
+            _ => Some(value.display_as(tag).to_string()),
         }
-        let reader = res_reader.unwrap();
-        for tag in Self::TAG_LIST {
-            if let Some(field) = reader.get_field(*tag, false) {
-                tag_map.insert(*tag, field.value.display_as(*tag).to_string());
-            }
+    }
+}
+
+/// Dates are written in the local time of the camera, without time zone.
+impl FromExifValue for NaiveDateTime {
+    fn from_exif_value(_tag: exif::Tag, value: &exif::Value) -> Option<Self> {
+        match value {
+            exif::Value::Ascii(lines) => {
+                let date = exif::DateTime::from_ascii(lines.first()?).ok()?;
+                NaiveDate::from_ymd_opt(i32::from(date.year), u32::from(date.month), u32::from(date.day))?
+                    .and_hms_opt(u32::from(date.hour), u32::from(date.minute), u32::from(date.second))
+            },
+            _ => None,
         }
-        Ok(tag_map)
+    }
+}
+
+impl FromExifValue for i32 {
+    fn from_exif_value(_tag: exif::Tag, value: &exif::Value) -> Option<Self> {
+        value.get_uint(0).map(|value| value as i32)
+    }
+}
+
+pub trait ExifExtractor {
+    /// Reads the EXIF data of the file, if it has any.
+    fn read_exif(path: &PathBuf) -> io::Result<Option<exif::Reader>> {
+        let file = fs::File::open(path)?;
+        Ok(exif::Reader::new(&mut io::BufReader::new(&file)).ok())
+    }
+
+    fn get_exif_value<T: FromExifValue>(reader: &exif::Reader, tag: exif::Tag) -> Option<T> {
+        reader.get_field(tag, false)
+            .and_then(|field| T::from_exif_value(tag, &field.value))
     }
 
     fn extract_exif(&mut self, path: &PathBuf) -> io::Result<()>;
@@ -1,28 +1,134 @@
-use actix_web::actix::Message;
+use actix_web::actix::{Addr, Message};
 use askama::Template;
+use chrono::NaiveDateTime;
+use futures::future::Future;
 
-use super::schema::jobs;
+use super::db::DbExecutor;
+use super::schema::{jobs, job_errors};
 use crate::error::GalleryError;
+use crate::auth::AdminContext;
+
+pub const JOB_INDEX_GALLERY: &str = "index_gallery";
+pub const JOB_WATCH_GALLERY: &str = "watch_gallery";
+pub const JOB_REBUILD_THUMBNAILS: &str = "rebuild_thumbnails";
+pub const JOB_PURGE_CACHE: &str = "purge_cache";
+pub const JOB_VERIFY_INTEGRITY: &str = "verify_integrity";
 
 pub const STATE_CREATED: &str = "created";
 pub const STATE_RUNNING: &str = "running";
 pub const STATE_FINISHED: &str = "finished";
+pub const STATE_FAILED: &str = "failed";
+pub const STATE_INTERRUPTED: &str = "interrupted";
+pub const STATE_CANCELLING: &str = "cancelling";
+pub const STATE_CANCELLED: &str = "cancelled";
+
+/// States of the jobs not completed yet, only one job of a given kind can be
+/// in one of these states at a time.
+pub const ACTIVE_STATES: &[&str] = &[STATE_CREATED, STATE_RUNNING, STATE_CANCELLING];
 
 
-#[derive(Insertable, Queryable)]
+#[derive(Insertable, Queryable, Serialize)]
 pub struct Job {
     pub id: String,
     pub name: String,
     pub state: String,
+    pub started_at: Option<NaiveDateTime>,
+    pub ended_at: Option<NaiveDateTime>,
+    pub files_seen: i32,
+    pub files_indexed: i32,
+    pub files_skipped: i32,
+    pub files_failed: i32,
+    pub current_directory: Option<String>,
+    pub failure_reason: Option<String>,
+    pub files_processed: i32,
+}
+
+impl Job {
+    pub fn is_cancellable(&self) -> bool {
+        self.name != JOB_WATCH_GALLERY && (self.state == STATE_CREATED || self.state == STATE_RUNNING)
+    }
+
+    /// Fails with `GalleryError::JobCancelled` if a cancellation was requested.
+    /// Blocking, meant to be called by the actors running the jobs.
+    pub fn check_cancelled(job_id: &str, db: &Addr<DbExecutor>) -> Result<(), GalleryError> {
+        let current_job = db.send(GetJob { job_id: job_id.to_string() }).wait()??;
+
+        if current_job.state == STATE_CANCELLING || current_job.state == STATE_CANCELLED {
+            return Err(GalleryError::JobCancelled);
+        }
+        Ok(())
+    }
+
+    /// Records the final state of a job from the result of its execution.
+    pub fn report_outcome(job_id: String, result: Result<(), GalleryError>, db: &Addr<DbExecutor>)
+        -> Result<(), GalleryError>
+    {
+        match result {
+            Err(GalleryError::JobCancelled) => {
+                info!("Job {} cancelled.", job_id);
+                db.do_send(ChangeState {
+                    job_id,
+                    new_state: STATE_CANCELLED.to_string(),
+                });
+                Ok(())
+            },
+            Err(err) => {
+                error!("Job {} failed, {}", job_id, err);
+                db.do_send(FailJob {
+                    job_id,
+                    reason: err.to_string(),
+                });
+                Err(err)
+            },
+            Ok(()) => {
+                db.do_send(ChangeState {
+                    job_id,
+                    new_state: STATE_FINISHED.to_string(),
+                });
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, AsChangeset)]
+#[table_name = "jobs"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct JobProgress {
+    pub files_seen: i32,
+    pub files_indexed: i32,
+    pub files_processed: i32,
+    pub files_skipped: i32,
+    pub files_failed: i32,
+    pub current_directory: Option<String>,
+}
+
+#[derive(Insertable, Queryable, Serialize)]
+pub struct JobError {
+    pub id: String,
+    pub job_id: String,
+    pub path: Option<String>,
+    pub message: String,
+    pub created_at: NaiveDateTime,
 }
 
 #[derive(Template)]
 #[template(path = "admin/jobs.html")]
 pub struct JobsTemplate {
+    pub admin: AdminContext,
     pub new_job_id: Option<String>,
+    pub error: Option<String>,
     pub jobs: Vec<Job>,
 }
 
+#[derive(Template)]
+#[template(path = "admin/job.html")]
+pub struct JobTemplate {
+    pub admin: AdminContext,
+    pub job: Job,
+    pub errors: Vec<JobError>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateJob {
     pub name: String,
@@ -33,8 +139,40 @@ pub struct ChangeState {
     pub new_state: String,
 }
 
+pub struct CancelJob {
+    pub job_id: String,
+}
+
+pub struct FailJob {
+    pub job_id: String,
+    pub reason: String,
+}
+
+/// Marks the jobs left running by a previous instance of the server as
+/// interrupted, and returns them.
+pub struct InterruptRunningJobs;
+
+pub struct UpdateProgress {
+    pub job_id: String,
+    pub progress: JobProgress,
+}
+
+pub struct LogError {
+    pub job_id: String,
+    pub path: Option<String>,
+    pub message: String,
+}
+
 pub struct GetJobs;
 
+pub struct GetJob {
+    pub job_id: String,
+}
+
+pub struct GetJobErrors {
+    pub job_id: String,
+}
+
 impl Message for CreateJob {
     type Result = Result<String, GalleryError>;
 }
@@ -43,6 +181,34 @@ impl Message for ChangeState {
     type Result = Result<(), GalleryError>;
 }
 
+impl Message for CancelJob {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for FailJob {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for InterruptRunningJobs {
+    type Result = Result<Vec<Job>, GalleryError>;
+}
+
+impl Message for UpdateProgress {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for LogError {
+    type Result = Result<(), GalleryError>;
+}
+
 impl Message for GetJobs {
     type Result = Result<Vec<Job>, GalleryError>;
 }
+
+impl Message for GetJob {
+    type Result = Result<Job, GalleryError>;
+}
+
+impl Message for GetJobErrors {
+    type Result = Result<Vec<JobError>, GalleryError>;
+}
@@ -1,11 +1,18 @@
 pub mod photo;
 pub mod album;
 pub mod photo_thumbnail;
+pub mod source_format;
+pub mod raw;
+pub mod video;
 pub mod album_thumbnail;
 pub mod helper;
 pub mod db;
 pub mod schema;
 pub mod job;
+pub mod user;
+pub mod share;
+pub mod search;
+pub mod timeline;
 
 pub use photo::{Photo, PhotoTemplate};
 pub use album::{Album, AlbumTemplate};
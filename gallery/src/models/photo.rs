@@ -1,33 +1,45 @@
 use std::{fs, io};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::time::UNIX_EPOCH;
 
 use actix_web::actix::{Addr, Message};
-use futures::future::Future;
+use futures::future::{self, Future};
 use askama::Template;
 use sha2::{Sha256, Digest};
+use chrono::NaiveDateTime;
 
 use gallery_derive::ExifExtractor;
 use super::db::DbExecutor;
 use super::schema::photos;
 use super::helper::ExifExtractor;
+use super::raw::RawFormat;
+use super::photo_thumbnail::{PhotoSort, ThumbnailSizes};
+use super::album::AccessGrant;
 use crate::error::GalleryError;
+use crate::utils;
 
 #[derive(Debug, Template)]
 #[template(path = "photo.html")]
 pub struct PhotoTemplate {
     // Keeping name here because currently required for breadcrumb templating
-    name: String,
+    pub name: String,
 
-    photo: Photo,
+    pub photo: Photo,
 
-    breadcrumb: Vec<(String, String)>,
-    album_path: String,
-    previous_photo: Option<String>,
-    next_photo: Option<String>,
+    pub breadcrumb: Vec<(String, String)>,
+    pub album_path: String,
+    pub previous_photo: Option<String>,
+    pub next_photo: Option<String>,
+    pub variants: Vec<Photo>,
+    pub thumbnail_sizes: ThumbnailSizes,
+    /// Query string keeping the order of the album asked for in the links.
+    pub sort_query: String,
 }
 
-#[derive(Debug, Insertable, Queryable, ExifExtractor, Default)]
+#[derive(Debug, Insertable, Identifiable, Queryable, AsChangeset, ExifExtractor, Default)]
 #[table_name = "photos"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Photo {
     pub id: String,
     pub name: String,
@@ -35,7 +47,7 @@ pub struct Photo {
     pub hash: String,
 
     #[exif(tag = "DateTimeOriginal", name = "Date")]
-    pub creation_date: Option<String>,
+    pub creation_date: Option<NaiveDateTime>,
     #[exif(tag = "Model", name = "Camera")]
     pub camera: Option<String>,
     #[exif(tag = "ExposureTime", name = "Exposure")]
@@ -48,40 +60,96 @@ pub struct Photo {
     pub focal_length_in_35mm: Option<String>,
     #[exif(tag = "Flash", name = "Flash" )]
     pub flash: Option<String>,
+
+    pub file_size: i64,
+    pub file_mtime: i64,
+
+    #[exif(tag = "Orientation")]
+    pub orientation: Option<i32>,
+    /// Dimensions of the photo once rotated according to its orientation.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// Version of the indexer which last read the file.
+    pub index_version: i32,
+    pub mime_type: Option<String>,
+    /// Set on the variants of a photo, such as the RAW file of a JPEG.
+    pub primary_photo_id: Option<String>,
+    /// Duration of videos in seconds.
+    pub duration: Option<f64>,
+    #[exif(tag = "ImageDescription", name = "Caption")]
+    pub caption: Option<String>,
+    #[exif(tag = "0x9c9e", name = "Keywords")]
+    pub keywords: Option<String>,
 }
 
 impl PhotoTemplate {
-    pub fn get(name: String, album_id: String, breadcrumb: Vec<(String, String)>, db: Addr<DbExecutor>)
+    /// The previous and next photos follow the order asked for, or the
+    /// default one of the album.
+    pub fn get(name: String, album_id: String, breadcrumb: Vec<(String, String)>, sort: Option<PhotoSort>, thumbnail_sizes: ThumbnailSizes,
+        grant: AccessGrant, db: Addr<DbExecutor>)
         -> impl Future<Item = Self, Error = GalleryError>
     {
         Photo::get(name, album_id, db.clone())
             .and_then(move |photo| {
-                let adj_future = db.send(GetAdjacentPhotos {
-                    name: photo.name.clone(),
-                    album_id: photo.album_id.clone()
-                });
-                adj_future.join(Ok(photo)).from_err()
+                // Photos shared on their own don't lead to the rest of their
+                // album, and variants are only offered for download.
+                let adj_future: Box<Future<Item = (Option<String>, Option<String>), Error = GalleryError>> = match grant {
+                    AccessGrant::SharedPhoto { .. } => Box::new(future::ok((None, None))),
+                    _ => Box::new(db.send(GetAdjacentPhotos {
+                        name: photo.name.clone(),
+                        album_id: photo.album_id.clone(),
+                        sort,
+                    }).from_err::<GalleryError>().flatten()),
+                };
+                let variants_future: Box<Future<Item = Vec<Photo>, Error = GalleryError>> = if grant.allows_download() {
+                    Box::new(db.send(GetPhotoVariants {
+                        photo_id: photo.id.clone(),
+                    }).from_err::<GalleryError>().flatten())
+                } else {
+                    Box::new(future::ok(Vec::new()))
+                };
+                adj_future.join(variants_future).join(Ok(photo))
             })
-            .and_then(move |(res, photo)| {
-                match res {
-                    Ok((prev, next)) => {
-                        let album_path = (&breadcrumb[breadcrumb.len() - 1].0).to_owned();
-
-                        Ok(PhotoTemplate {
-                            name: photo.name.clone(),
-
-                            photo: photo,
-                            breadcrumb: breadcrumb,
-                            album_path: album_path,
-
-                            previous_photo: prev,
-                            next_photo: next,
-                        })
-                    },
-                    Err(e) => Err(e)
-                }
+            .and_then(move |(((prev, next), variants), photo)| {
+                let album_path = (&breadcrumb[breadcrumb.len() - 1].0).to_owned();
+
+                Ok(PhotoTemplate {
+                    name: photo.name.clone(),
+
+                    photo: photo,
+                    breadcrumb: breadcrumb,
+                    album_path: album_path,
+
+                    previous_photo: prev,
+                    next_photo: next,
+                    variants,
+                    thumbnail_sizes,
+                    sort_query: sort.map(PhotoSort::get_query).unwrap_or_default(),
+                })
             })
     }
+
+    pub fn get_src(&self) -> String {
+        let path = format!("{}/{}", self.album_path, self.photo.name);
+        self.thumbnail_sizes.get_default_url(&path, &self.photo.hash)
+    }
+
+    pub fn get_srcset(&self) -> String {
+        let path = format!("{}/{}", self.album_path, self.photo.name);
+        self.thumbnail_sizes.get_srcset(&path, &self.photo.hash, self.photo.width, self.photo.height)
+    }
+
+    /// The photo is displayed between the navigation links, each taking a
+    /// tenth of the page.
+    pub fn get_sizes(&self) -> String {
+        self.thumbnail_sizes.get_sizes(self.photo.width, self.photo.height, 80)
+    }
+
+    /// Dimensions of the default thumbnail, letting the browser lay out the
+    /// page before it is loaded.
+    pub fn get_thumbnail_dimensions(&self) -> Option<(u32, u32)> {
+        self.thumbnail_sizes.get_default_dimensions(self.photo.width, self.photo.height)
+    }
 }
 
 impl Photo {
@@ -100,6 +168,66 @@ impl Photo {
         let hash = format!("{:x}", hasher.result());
         Ok(hash)
     }
+
+    pub fn read_file_state(&mut self, path: &PathBuf) -> io::Result<()> {
+        let metadata = fs::metadata(path)?;
+        self.file_size = metadata.len() as i64;
+        self.file_mtime = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0);
+        Ok(())
+    }
+
+    /// Files sharing the same name without their extension are grouped as
+    /// a single photo. Videos are never stacked, the clips of live photos
+    /// being listed along their photo.
+    pub fn get_stack_name(&self) -> Option<String> {
+        if self.is_video() {
+            return None;
+        }
+        Path::new(&self.name).file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .or_else(|| Some(self.name.clone()))
+    }
+
+    /// Whether browsers can't display the file, making it a poor choice to
+    /// represent its stack.
+    pub fn is_raw(&self) -> bool {
+        self.mime_type.as_ref()
+            .map(|mime_type| RawFormat::is_raw_mime_type(mime_type))
+            .unwrap_or(false)
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.mime_type.as_ref()
+            .map(|mime_type| mime_type.starts_with("video/"))
+            .unwrap_or(false)
+    }
+
+    pub fn has_same_file_state(&self, other: &Photo) -> bool {
+        self.file_size == other.file_size && self.file_mtime == other.file_mtime
+    }
+
+    pub fn get_readable_size(&self) -> String {
+        utils::format_file_size(self.file_size)
+    }
+
+    /// Properties of the file itself, displayed along the EXIF metadata.
+    pub fn get_file_metadata(&self) -> Vec<(&str, String)> {
+        let mut res = Vec::new();
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            res.push(("Dimensions", format!("{}×{}", width, height)));
+        }
+        if let Some(duration) = self.duration {
+            res.push(("Duration", utils::format_duration(duration)));
+        }
+        res.push(("Size", self.get_readable_size()));
+        if let Some(ref mime_type) = self.mime_type {
+            res.push(("Format", mime_type.clone()));
+        }
+        res
+    }
 }
 
 pub struct CreatePhoto {
@@ -111,14 +239,48 @@ pub struct GetPhoto {
     pub album_id: String,
 }
 
-pub struct GetPhotoId {
+pub struct FindPhoto {
     pub name: String,
     pub album_id: String,
 }
 
+pub struct GetPhotosByHash {
+    pub hash: String,
+}
+
+pub struct GetAlbumPhotoNames {
+    pub album_id: String,
+}
+
+pub struct UpdatePhoto {
+    pub photo: Photo,
+}
+
+pub struct GetAllPhotos;
+
+pub struct GetPhotoHashes;
+
+pub struct DeletePhoto {
+    pub photo_id: String,
+}
+
+pub struct DeleteStalePhotos {
+    pub seen_photos: HashSet<String>,
+}
+
+pub struct StackPhotos {
+    pub photo_ids: Vec<String>,
+}
+
+pub struct GetPhotoVariants {
+    pub photo_id: String,
+}
+
 pub struct GetAdjacentPhotos {
     pub name: String,
     pub album_id: String,
+    /// The default order of the album when not set.
+    pub sort: Option<PhotoSort>,
 }
 
 impl Message for CreatePhoto {
@@ -129,8 +291,44 @@ impl Message for GetPhoto {
     type Result = Result<Photo, GalleryError>;
 }
 
-impl Message for GetPhotoId {
-    type Result = Result<Option<String>, GalleryError>;
+impl Message for FindPhoto {
+    type Result = Result<Option<Photo>, GalleryError>;
+}
+
+impl Message for GetPhotosByHash {
+    type Result = Result<Vec<Photo>, GalleryError>;
+}
+
+impl Message for GetAlbumPhotoNames {
+    type Result = Result<HashMap<String, String>, GalleryError>;
+}
+
+impl Message for UpdatePhoto {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for GetAllPhotos {
+    type Result = Result<Vec<Photo>, GalleryError>;
+}
+
+impl Message for GetPhotoHashes {
+    type Result = Result<HashSet<String>, GalleryError>;
+}
+
+impl Message for DeletePhoto {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for DeleteStalePhotos {
+    type Result = Result<usize, GalleryError>;
+}
+
+impl Message for StackPhotos {
+    type Result = Result<(), GalleryError>;
+}
+
+impl Message for GetPhotoVariants {
+    type Result = Result<Vec<Photo>, GalleryError>;
 }
 
 impl Message for GetAdjacentPhotos {
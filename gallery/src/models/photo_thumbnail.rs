@@ -1,54 +1,345 @@
-use std::fs;
-use std::path::PathBuf;
+use std::{env, fs, io};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::UNIX_EPOCH;
 
-use image::GenericImageView;
+use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer, Rgb};
 use actix_web::actix::Message;
+use chrono::NaiveDateTime;
+use sha2::{Sha256, Digest};
 
-use crate::config::ThumbnailConfig;
+use crate::config::{Config, EncodersConfig, ThumbnailConfig, ThumbnailFormat};
 use crate::error::GalleryError;
 
+/// Number of hexadecimal characters of the SHA-256 hashes of the photos.
+const HASH_LENGTH: usize = 64;
+
+/// Number of characters of the UUIDs in the names of the temporary files.
+const UUID_LENGTH: usize = 36;
+
 #[derive(Debug, Queryable)]
 pub struct PhotoThumbnail {
     pub name: String,
-    pub creation_date: Option<String>,
+    pub creation_date: Option<NaiveDateTime>,
+    pub mime_type: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub hash: String,
+    pub file_mtime: i64,
+}
+
+/// Number of hexadecimal characters of the versions in the URLs of the
+/// thumbnails, so that they can be cached indefinitely.
+const VERSION_LENGTH: usize = 16;
+
+/// Thumbnails offered to the browsers in the srcset of the images, from the
+/// smallest to the largest.
+#[derive(Debug, Clone)]
+pub struct ThumbnailSizes {
+    sizes: Vec<(String, ThumbnailConfig)>,
+    cache_path: String,
+}
+
+/// Orders in which the photos of an album can be listed, photos with the
+/// same date or modification time being ordered by name.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PhotoSort {
+    #[default]
+    NameAsc,
+    NameDesc,
+    DateAsc,
+    DateDesc,
+    MtimeAsc,
+    MtimeDesc,
 }
 
+/// Loads a page of the photos of an album, along with their total number.
 pub struct GetPhotosThumbnail {
     pub parent_album_id: String,
+    pub sort: PhotoSort,
+    pub offset: i64,
+    pub limit: i64,
 }
 
 impl Message for GetPhotosThumbnail {
-    type Result = Result<Vec<PhotoThumbnail>, GalleryError>;
+    type Result = Result<(Vec<PhotoThumbnail>, i64), GalleryError>;
+}
+
+impl PhotoSort {
+    pub const ALL: &'static [PhotoSort] = &[
+        PhotoSort::NameAsc,
+        PhotoSort::NameDesc,
+        PhotoSort::DateAsc,
+        PhotoSort::DateDesc,
+        PhotoSort::MtimeAsc,
+        PhotoSort::MtimeDesc,
+    ];
+
+    /// Value of the `sort` query parameter, also stored as the default order
+    /// of the albums.
+    pub fn as_param(self) -> &'static str {
+        match self {
+            PhotoSort::NameAsc => "name_asc",
+            PhotoSort::NameDesc => "name_desc",
+            PhotoSort::DateAsc => "date_asc",
+            PhotoSort::DateDesc => "date_desc",
+            PhotoSort::MtimeAsc => "mtime_asc",
+            PhotoSort::MtimeDesc => "mtime_desc",
+        }
+    }
+
+    /// Query string of the links keeping this order.
+    pub fn get_query(self) -> String {
+        format!("?sort={}", self.as_param())
+    }
+
+    pub fn from_param(param: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|sort| sort.as_param() == param)
+    }
+
+    pub fn get_label(self) -> &'static str {
+        match self {
+            PhotoSort::NameAsc => "Name, A to Z",
+            PhotoSort::NameDesc => "Name, Z to A",
+            PhotoSort::DateAsc => "Oldest first",
+            PhotoSort::DateDesc => "Newest first",
+            PhotoSort::MtimeAsc => "Least recently modified",
+            PhotoSort::MtimeDesc => "Recently modified",
+        }
+    }
+
+    /// The same order in the other direction.
+    pub fn reversed(self) -> Self {
+        match self {
+            PhotoSort::NameAsc => PhotoSort::NameDesc,
+            PhotoSort::NameDesc => PhotoSort::NameAsc,
+            PhotoSort::DateAsc => PhotoSort::DateDesc,
+            PhotoSort::DateDesc => PhotoSort::DateAsc,
+            PhotoSort::MtimeAsc => PhotoSort::MtimeDesc,
+            PhotoSort::MtimeDesc => PhotoSort::MtimeAsc,
+        }
+    }
+
+    pub fn is_descending(self) -> bool {
+        match self {
+            PhotoSort::NameDesc | PhotoSort::DateDesc | PhotoSort::MtimeDesc => true,
+            PhotoSort::NameAsc | PhotoSort::DateAsc | PhotoSort::MtimeAsc => false,
+        }
+    }
 }
 
 impl PhotoThumbnail {
-    pub fn create_image(path: &PathBuf, hash: &String, thumbnail_config: &ThumbnailConfig, cache_path: String) -> Result<PathBuf, GalleryError> {
+    pub fn is_video(&self) -> bool {
+        self.mime_type.as_ref()
+            .map(|mime_type| mime_type.starts_with("video/"))
+            .unwrap_or(false)
+    }
+
+    /// Version of a thumbnail in its URLs, changing with the photo, the
+    /// configuration of the thumbnail and its generation.
+    pub fn get_version(hash: &String, thumbnail_config: &ThumbnailConfig, cache_path: String) -> String {
+        let thumbnail_path = Self::get_image_path(hash, thumbnail_config, cache_path);
+        // Thumbnails rendered on demand get a new version once generated.
+        let generated_at = fs::metadata(&thumbnail_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        let formats: Vec<&str> = thumbnail_config.formats.iter().map(|format| format.extension()).collect();
+
+        let digest = Sha256::digest(format!(
+            "{}:{}:{}:{}:{}:{}",
+            hash, thumbnail_config.size, thumbnail_config.square, thumbnail_config.extension, formats.join(","), generated_at
+        ).as_bytes());
+        digest.iter().take(VERSION_LENGTH / 2).map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn create_image(img: &DynamicImage, hash: &String, thumbnail_config: &ThumbnailConfig, encoders: &EncodersConfig, cache_path: String) -> Result<PathBuf, GalleryError> {
         let thumbnail_path = Self::get_image_path(&hash, thumbnail_config, cache_path);
 
-        if thumbnail_path.is_file() {
+        let complete = thumbnail_path.is_file() && thumbnail_config.formats.iter()
+            .all(|&format| Self::get_format_path(&thumbnail_path, format).is_file());
+        if complete {
             debug!("File {:?} already exists.", thumbnail_path);
             return Ok(thumbnail_path);
         }
 
+        Self::generate_image(img, thumbnail_path, thumbnail_config, encoders)
+    }
+
+    /// Writes the thumbnail of the image in all its formats, replacing any
+    /// existing one.
+    pub fn generate_image(img: &DynamicImage, thumbnail_path: PathBuf, thumbnail_config: &ThumbnailConfig, encoders: &EncodersConfig) -> Result<PathBuf, GalleryError> {
         let ThumbnailConfig { size, square, .. } = *thumbnail_config;
 
-        let img = image::open(&path)?;
         let (width, height) = img.dimensions();
 
         let thumbnail = if width < size && height < size {
-            img
+            img.clone()
         } else if square {
             img.resize_to_fill(size, size, image::FilterType::Gaussian)
         } else {
             img.resize(size, size, image::FilterType::Gaussian)
         };
 
+        let thumbnail = if Self::supports_transparency(&thumbnail_path) {
+            thumbnail
+        } else {
+            Self::flatten(thumbnail)
+        };
+
+        // Files are written aside and moved in place once complete, so that
+        // a thumbnail being generated is never served partially. The main
+        // format comes last, as the other ones are looked for once it exists.
         fs::create_dir_all(thumbnail_path.parent().unwrap())?;
-        thumbnail.save(&thumbnail_path)?;
+        let main_path = Self::get_temporary_path(&thumbnail_path);
+        if let Err(err) = thumbnail.save(&main_path) {
+            let _ = fs::remove_file(&main_path);
+            return Err(err.into());
+        }
+
+        // The main format is always available, the page is still served when
+        // an additional one can't be produced.
+        for &format in &thumbnail_config.formats {
+            let format_path = Self::get_format_path(&thumbnail_path, format);
+            let temporary_path = Self::get_temporary_path(&format_path);
+            match Self::encode(&thumbnail, &temporary_path, format, encoders) {
+                Ok(()) => fs::rename(&temporary_path, &format_path)?,
+                Err(err) => {
+                    warn!("Could not write {:?}, {}", format_path, err);
+                    // Don't serve the one of a previous generation instead.
+                    if format_path.exists() {
+                        fs::remove_file(&format_path)?;
+                    }
+                },
+            }
+        }
 
+        fs::rename(&main_path, &thumbnail_path)?;
         Ok(thumbnail_path)
     }
 
+    /// Dimensions of the thumbnail of an image of the given dimensions,
+    /// following the resizing done in `generate_image`.
+    pub fn get_dimensions(thumbnail_config: &ThumbnailConfig, width: u32, height: u32) -> (u32, u32) {
+        let size = thumbnail_config.size;
+
+        if width < size && height < size {
+            (width, height)
+        } else if thumbnail_config.square {
+            (size, size)
+        } else if width >= height {
+            (size, (height as u64 * size as u64 / width as u64).max(1) as u32)
+        } else {
+            ((width as u64 * size as u64 / height as u64).max(1) as u32, size)
+        }
+    }
+
+    /// Hash of the photo of a thumbnail from the name of its file, `None`
+    /// when the name isn't the one of a configured thumbnail or of one being
+    /// written.
+    pub fn get_hash_from_name<'a>(name: &'a str, config: &Config) -> Option<&'a str> {
+        let (hash, extension) = name.split_at(name.find('.')?);
+        if hash.len() != HASH_LENGTH || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        // See `get_temporary_path`.
+        let name = match extension.strip_prefix(".tmp-") {
+            Some(rest) => {
+                let uuid = rest.get(..UUID_LENGTH)?;
+                uuid::Uuid::parse_str(uuid).ok()?;
+                format!("{}{}", hash, &rest[UUID_LENGTH..])
+            },
+            None => name.to_string(),
+        };
+
+        let is_thumbnail = config.thumbnails.values().any(|thumbnail_config| {
+            let thumbnail_path = Self::get_image_path(&hash.to_string(), thumbnail_config, String::new());
+            thumbnail_path == Path::new(&name) || thumbnail_config.formats.iter()
+                .any(|&format| Self::get_format_path(&thumbnail_path, format) == Path::new(&name))
+        });
+        if is_thumbnail {
+            Some(hash)
+        } else {
+            None
+        }
+    }
+
+    /// Path next to the given one, starting with the same hash and keeping
+    /// its extension, the encoders choosing the format from it.
+    fn get_temporary_path(path: &Path) -> PathBuf {
+        let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let (hash, extension) = name.split_at(name.find('.').unwrap_or(name.len()));
+        path.with_file_name(format!("{}.tmp-{}{}", hash, uuid::Uuid::new_v4(), extension))
+    }
+
+    /// Removes the thumbnail in all its formats, so that it is generated
+    /// again.
+    pub fn remove_image(hash: &String, thumbnail_config: &ThumbnailConfig, cache_path: String) -> Result<(), GalleryError> {
+        let thumbnail_path = Self::get_image_path(hash, thumbnail_config, cache_path);
+        let format_paths = thumbnail_config.formats.iter().map(|&format| Self::get_format_path(&thumbnail_path, format));
+        for path in format_paths.chain(Some(thumbnail_path.clone())) {
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts the thumbnail with the external program configured for the
+    /// format, going through a temporary PNG file.
+    fn encode(img: &DynamicImage, format_path: &Path, format: ThumbnailFormat, encoders: &EncodersConfig) -> Result<(), GalleryError> {
+        let command = encoders.get(format)
+            .ok_or_else(|| GalleryError::EncoderError(format!("no encoder configured for {}", format.extension())))?;
+
+        let source_path = env::temp_dir().join(format!("gallery-{}.png", uuid::Uuid::new_v4()));
+        img.save(&source_path)?;
+
+        let output = Command::new(command).arg(&source_path).arg(format_path).output();
+        fs::remove_file(&source_path)?;
+
+        let result = match output {
+            Ok(ref output) if output.status.success() && format_path.is_file() => Ok(()),
+            Ok(output) => Err(GalleryError::EncoderError(format!(
+                "{} exited with {}, {}", command, output.status, String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+            Err(err) => Err(GalleryError::EncoderError(format!("could not run {}, {}", command, err))),
+        };
+
+        // Don't leave a partial file that would be served afterwards.
+        if result.is_err() && format_path.exists() {
+            fs::remove_file(format_path)?;
+        }
+        result
+    }
+
+    fn supports_transparency(thumbnail_path: &Path) -> bool {
+        thumbnail_path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase() == "png")
+            .unwrap_or(false)
+    }
+
+    /// Blends transparent images on a white background.
+    fn flatten(img: DynamicImage) -> DynamicImage {
+        match img.color() {
+            ColorType::RGBA(_) | ColorType::BGRA(_) | ColorType::GrayA(_) => (),
+            _ => return img,
+        }
+
+        let rgba = img.to_rgba();
+        let rgb = ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let [red, green, blue, alpha] = rgba.get_pixel(x, y).data;
+            let blend = |value: u8| ((value as u32 * alpha as u32 + 255 * (255 - alpha as u32)) / 255) as u8;
+            Rgb([blend(red), blend(green), blend(blue)])
+        });
+        DynamicImage::ImageRgb8(rgb)
+    }
+
     pub fn get_image_path(hash: &String, thumbnail_config: &ThumbnailConfig, cache_path: String) -> PathBuf {
         let extension = &thumbnail_config.extension;
 
@@ -57,4 +348,126 @@ impl PhotoThumbnail {
         thumbnail_path.push(hash);
         thumbnail_path.with_extension(extension)
     }
+
+    /// Path of the thumbnail in an additional format, next to the main one.
+    pub fn get_format_path(thumbnail_path: &Path, format: ThumbnailFormat) -> PathBuf {
+        thumbnail_path.with_extension(format.extension())
+    }
+
+    /// Orders the additional formats by the preference given in the Accept
+    /// header. Only the formats named explicitly are kept, as browsers sending
+    /// wildcards don't necessarily decode them.
+    pub fn get_accepted_formats(accept: &str, formats: &[ThumbnailFormat]) -> Vec<ThumbnailFormat> {
+        let mut accepted: Vec<(ThumbnailFormat, f32)> = formats.iter()
+            .filter_map(|&format| {
+                accept.split(',')
+                    .filter_map(|media_range| {
+                        let mut params = media_range.split(';');
+                        let media_type = params.next()?.trim();
+                        if !media_type.eq_ignore_ascii_case(format.mime_type()) {
+                            return None;
+                        }
+                        let quality = params
+                            .filter_map(|param| param.trim().strip_prefix("q=").and_then(|quality| quality.parse().ok()))
+                            .next()
+                            .unwrap_or(1.0);
+                        Some(quality)
+                    })
+                    .next()
+                    .filter(|&quality| quality > 0.0)
+                    .map(|quality| (format, quality))
+            })
+            .collect();
+
+        // The sort is stable, equal preferences keep the order of the config.
+        accepted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        accepted.into_iter().map(|(format, _)| format).collect()
+    }
+}
+
+impl ThumbnailSizes {
+    /// Keeps the square thumbnails or the other ones, or all of them when
+    /// none are configured this way.
+    pub fn new(config: &Config, square: bool) -> Self {
+        let thumbnails = &config.thumbnails;
+        let mut sizes: Vec<_> = thumbnails.iter()
+            .filter(|(_, thumbnail_config)| thumbnail_config.square == square)
+            .map(|(name, thumbnail_config)| (name.clone(), thumbnail_config.clone()))
+            .collect();
+        if sizes.is_empty() {
+            sizes = thumbnails.iter()
+                .map(|(name, thumbnail_config)| (name.clone(), thumbnail_config.clone()))
+                .collect();
+        }
+        sizes.sort_by_key(|(_, thumbnail_config)| thumbnail_config.size);
+        ThumbnailSizes { sizes, cache_path: config.cache_path.clone() }
+    }
+
+    fn get_url(&self, path: &str, hash: &String, name: &str, thumbnail_config: &ThumbnailConfig) -> String {
+        let version = PhotoThumbnail::get_version(hash, thumbnail_config, self.cache_path.clone());
+        format!("{}/{}?v={}", path, name, version)
+    }
+
+    /// URL of the smallest size for the photo at the given path, used by
+    /// browsers ignoring srcset.
+    pub fn get_default_url(&self, path: &str, hash: &String) -> String {
+        match self.sizes.first() {
+            Some((name, thumbnail_config)) => self.get_url(path, hash, name, thumbnail_config),
+            None => String::new(),
+        }
+    }
+
+    /// Width of the thumbnail of a photo. Photos of unknown dimensions are
+    /// assumed to be large landscape ones.
+    pub fn get_width(thumbnail_config: &ThumbnailConfig, width: Option<i32>, height: Option<i32>) -> u32 {
+        match (width, height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => {
+                PhotoThumbnail::get_dimensions(thumbnail_config, width as u32, height as u32).0
+            },
+            _ => thumbnail_config.size,
+        }
+    }
+
+    /// Dimensions of the smallest size for a photo, None when the ones of the
+    /// photo are unknown.
+    pub fn get_default_dimensions(&self, width: Option<i32>, height: Option<i32>) -> Option<(u32, u32)> {
+        let (_, thumbnail_config) = self.sizes.first()?;
+        match (width, height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => {
+                Some(PhotoThumbnail::get_dimensions(thumbnail_config, width as u32, height as u32))
+            },
+            _ => None,
+        }
+    }
+
+    /// Candidates of the srcset attribute for the photo at the given path,
+    /// sizes giving thumbnails of the same width are only listed once.
+    pub fn get_srcset(&self, path: &str, hash: &String, width: Option<i32>, height: Option<i32>) -> String {
+        // Spaces and commas separate the candidates.
+        let path = path.replace(' ', "%20").replace(',', "%2C");
+
+        let mut widths = Vec::new();
+        let mut candidates = Vec::new();
+        for (name, thumbnail_config) in &self.sizes {
+            let thumbnail_width = Self::get_width(thumbnail_config, width, height);
+            if !widths.contains(&thumbnail_width) {
+                widths.push(thumbnail_width);
+                candidates.push(format!("{} {}w", self.get_url(&path, hash, name, thumbnail_config), thumbnail_width));
+            }
+        }
+        candidates.join(", ")
+    }
+
+    /// Sizes attribute displaying the photo as large as its default thumbnail,
+    /// within the given share of the viewport width.
+    pub fn get_sizes(&self, width: Option<i32>, height: Option<i32>, viewport_share: u32) -> String {
+        let default_width = match self.sizes.first() {
+            Some((_, thumbnail_config)) => Self::get_width(thumbnail_config, width, height),
+            None => return "100vw".to_string(),
+        };
+        format!(
+            "(max-width: {}px) {}vw, {}px",
+            default_width * 100 / viewport_share, viewport_share, default_width
+        )
+    }
 }
@@ -0,0 +1,480 @@
+use std::{fs, io};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::collections::HashSet;
+
+use image::{DynamicImage, ImageFormat};
+
+// TIFF tags used to find the embedded previews.
+const TAG_MAKE: u16 = 0x010f;
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014a;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+const TAG_DNG_VERSION: u16 = 0xc612;
+
+/// Compression values of the image data stored as JPEG.
+const JPEG_COMPRESSIONS: [u32; 2] = [6, 7];
+
+/// Limits guarding against corrupted files.
+const MAX_IFD_ENTRIES: u16 = 1024;
+const MAX_IFDS: usize = 32;
+const MAX_VALUES: u32 = 4096;
+
+/// Camera RAW formats, all built on the TIFF structure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RawFormat {
+    Cr2,
+    Nef,
+    Arw,
+    Dng,
+}
+
+impl RawFormat {
+    /// Tells apart RAW files from plain TIFF images. Nikon and Sony files
+    /// have no signature of their own and are recognized by their maker.
+    pub fn detect(path: &PathBuf) -> io::Result<Option<Self>> {
+        let mut reader = match TiffReader::new(io::BufReader::new(fs::File::open(path)?))? {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+
+        if reader.read_signature()? == *b"CR" {
+            return Ok(Some(RawFormat::Cr2));
+        }
+
+        let offset = reader.first_ifd_offset()?;
+        let ifd = reader.read_ifd(offset)?;
+        if ifd.get(TAG_DNG_VERSION).is_some() {
+            return Ok(Some(RawFormat::Dng));
+        }
+
+        let make = match ifd.get(TAG_MAKE) {
+            Some(entry) => reader.read_string(entry)?.to_uppercase(),
+            None => return Ok(None),
+        };
+
+        if make.starts_with("NIKON") {
+            Ok(Some(RawFormat::Nef))
+        } else if make.starts_with("SONY") {
+            Ok(Some(RawFormat::Arw))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_raw_mime_type(mime_type: &str) -> bool {
+        [RawFormat::Cr2, RawFormat::Nef, RawFormat::Arw, RawFormat::Dng].iter()
+            .any(|raw_format| raw_format.mime_type() == mime_type)
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            RawFormat::Cr2 => "image/x-canon-cr2",
+            RawFormat::Nef => "image/x-nikon-nef",
+            RawFormat::Arw => "image/x-sony-arw",
+            RawFormat::Dng => "image/x-adobe-dng",
+        }
+    }
+
+    /// Decodes the largest JPEG preview embedded by the camera, if any.
+    pub fn extract_preview(path: &PathBuf) -> io::Result<Option<DynamicImage>> {
+        let mut reader = match TiffReader::new(io::BufReader::new(fs::File::open(path)?))? {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+
+        let mut candidates = reader.find_jpeg_data()?;
+        candidates.sort_by_key(|&(_, length)| std::cmp::Reverse(length));
+
+        for (offset, length) in candidates {
+            let data = reader.read_bytes(offset, length)?;
+            // The RAW data itself can be stored as lossless JPEG, which the
+            // decoder rejects, so keep looking for a regular one.
+            if !data.starts_with(&[0xff, 0xd8]) {
+                continue;
+            }
+            match image::load_from_memory_with_format(&data, ImageFormat::JPEG) {
+                Ok(img) => return Ok(Some(img)),
+                Err(err) => debug!("Skipping embedded image at {}, {}", offset, err),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+struct IfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    /// The value itself when it fits in four bytes, its offset otherwise.
+    value: [u8; 4],
+}
+
+struct Ifd {
+    entries: Vec<IfdEntry>,
+    next_offset: u32,
+}
+
+impl Ifd {
+    fn get(&self, tag: u16) -> Option<&IfdEntry> {
+        self.entries.iter().find(|entry| entry.tag == tag)
+    }
+}
+
+struct TiffReader<R> {
+    reader: R,
+    big_endian: bool,
+}
+
+impl<R: Read + Seek> TiffReader<R> {
+    fn new(mut reader: R) -> io::Result<Option<Self>> {
+        let mut byte_order = [0; 4];
+        reader.read_exact(&mut byte_order)?;
+
+        let big_endian = match &byte_order {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return Ok(None),
+        };
+        Ok(Some(TiffReader { reader, big_endian }))
+    }
+
+    /// Bytes following the header, where some formats put their own mark.
+    fn read_signature(&mut self) -> io::Result<[u8; 2]> {
+        let mut signature = [0; 2];
+        self.reader.seek(SeekFrom::Start(8))?;
+        self.reader.read_exact(&mut signature)?;
+        Ok(signature)
+    }
+
+    fn first_ifd_offset(&mut self) -> io::Result<u32> {
+        self.reader.seek(SeekFrom::Start(4))?;
+        self.read_u32()
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(self.u16_from(&bytes))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(self.u32_from(&bytes))
+    }
+
+    fn u16_from(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn u32_from(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    fn read_bytes(&mut self, offset: u32, length: u32) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.reader.seek(SeekFrom::Start(offset as u64))?;
+        (&mut self.reader).take(length as u64).read_to_end(&mut data)?;
+        if data.len() < length as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "TIFF data past the end of the file"));
+        }
+        Ok(data)
+    }
+
+    fn read_ifd(&mut self, offset: u32) -> io::Result<Ifd> {
+        self.reader.seek(SeekFrom::Start(offset as u64))?;
+        let count = self.read_u16()?;
+        if count > MAX_IFD_ENTRIES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many entries in TIFF directory"));
+        }
+
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let tag = self.read_u16()?;
+            let field_type = self.read_u16()?;
+            let count = self.read_u32()?;
+            let mut value = [0; 4];
+            self.reader.read_exact(&mut value)?;
+            entries.push(IfdEntry { tag, field_type, count, value });
+        }
+
+        let next_offset = self.read_u32()?;
+        Ok(Ifd { entries, next_offset })
+    }
+
+    /// Reads the values of a SHORT, LONG or IFD entry.
+    fn read_numbers(&mut self, entry: &IfdEntry) -> io::Result<Vec<u32>> {
+        let size = match entry.field_type {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Ok(Vec::new()),
+        };
+        if entry.count > MAX_VALUES {
+            return Ok(Vec::new());
+        }
+
+        let data = if size * entry.count <= 4 {
+            entry.value.to_vec()
+        } else {
+            let offset = self.u32_from(&entry.value);
+            self.read_bytes(offset, size * entry.count)?
+        };
+
+        Ok(data.chunks_exact(size as usize)
+            .take(entry.count as usize)
+            .map(|bytes| if size == 2 { self.u16_from(bytes) as u32 } else { self.u32_from(bytes) })
+            .collect())
+    }
+
+    fn read_string(&mut self, entry: &IfdEntry) -> io::Result<String> {
+        if entry.field_type != 2 || entry.count > MAX_VALUES {
+            return Ok(String::new());
+        }
+
+        let data = if entry.count <= 4 {
+            entry.value[..entry.count as usize].to_vec()
+        } else {
+            let offset = self.u32_from(&entry.value);
+            self.read_bytes(offset, entry.count)?
+        };
+        Ok(String::from_utf8_lossy(&data).trim_end_matches('\0').to_string())
+    }
+
+    fn read_number(&mut self, ifd: &Ifd, tag: u16) -> io::Result<Option<u32>> {
+        match ifd.get(tag) {
+            Some(entry) => Ok(self.read_numbers(entry)?.first().cloned()),
+            None => Ok(None),
+        }
+    }
+
+    /// Walks through all the directories of the file and lists the offset
+    /// and length of the JPEG data they reference.
+    fn find_jpeg_data(&mut self) -> io::Result<Vec<(u32, u32)>> {
+        let mut candidates = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![self.first_ifd_offset()?];
+
+        while let Some(offset) = pending.pop() {
+            if offset == 0 {
+                continue;
+            }
+            if visited.len() >= MAX_IFDS || !visited.insert(offset) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "loop in TIFF directories"));
+            }
+            let ifd = self.read_ifd(offset)?;
+
+            if let (Some(offset), Some(length)) = (
+                self.read_number(&ifd, TAG_JPEG_OFFSET)?,
+                self.read_number(&ifd, TAG_JPEG_LENGTH)?,
+            ) {
+                candidates.push((offset, length));
+            }
+
+            let compression = self.read_number(&ifd, TAG_COMPRESSION)?;
+            if compression.map(|value| JPEG_COMPRESSIONS.contains(&value)).unwrap_or(false) {
+                if let (Some(offsets), Some(lengths)) = (ifd.get(TAG_STRIP_OFFSETS), ifd.get(TAG_STRIP_BYTE_COUNTS)) {
+                    let offsets = self.read_numbers(offsets)?;
+                    let lengths = self.read_numbers(lengths)?;
+                    // Only images stored in a single strip are complete JPEG files.
+                    if offsets.len() == 1 && lengths.len() == 1 {
+                        candidates.push((offsets[0], lengths[0]));
+                    }
+                }
+            }
+
+            if let Some(entry) = ifd.get(TAG_SUB_IFDS) {
+                pending.extend(self.read_numbers(entry)?);
+            }
+            pending.push(ifd.next_offset);
+        }
+
+        Ok(candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use image::{GenericImageView, ImageOutputFormat, RgbImage};
+
+    use super::*;
+
+    const TYPE_ASCII: u16 = 2;
+    const TYPE_LONG: u16 = 4;
+
+    /// TIFF file written in memory, its directories being appended one after
+    /// the other from offset 8.
+    struct Fixture {
+        big_endian: bool,
+        data: Vec<u8>,
+    }
+
+    impl Fixture {
+        fn new(big_endian: bool) -> Self {
+            let mut fixture = Fixture { big_endian, data: Vec::new() };
+            fixture.data.extend_from_slice(if big_endian { b"MM\0*" } else { b"II*\0" });
+            fixture.push_u32(8);
+            fixture
+        }
+
+        fn push_u16(&mut self, value: u16) {
+            let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            self.data.extend_from_slice(&bytes);
+        }
+
+        fn push_u32(&mut self, value: u32) {
+            let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            self.data.extend_from_slice(&bytes);
+        }
+
+        /// Appends a directory of (tag, type, count, value) entries.
+        fn push_ifd(&mut self, entries: &[(u16, u16, u32, u32)], next_offset: u32) {
+            self.push_u16(entries.len() as u16);
+            for &(tag, field_type, count, value) in entries {
+                self.push_u16(tag);
+                self.push_u16(field_type);
+                self.push_u32(count);
+                self.push_u32(value);
+            }
+            self.push_u32(next_offset);
+        }
+
+        /// Offset the data following a directory of the given number of
+        /// entries starting at offset 8 will be written at.
+        fn data_offset(entries: usize) -> u32 {
+            8 + 2 + 12 * entries as u32 + 4
+        }
+
+        fn write(&self) -> TempFile {
+            let path = env::temp_dir().join(format!("gallery-test-{}.raw", uuid::Uuid::new_v4()));
+            fs::write(&path, &self.data).unwrap();
+            TempFile(path)
+        }
+    }
+
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn preview_jpeg() -> Vec<u8> {
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(3, 2)).write_to(&mut data, ImageOutputFormat::JPEG(90)).unwrap();
+        data
+    }
+
+    /// RAW file of the given maker with its preview referenced from the
+    /// first directory.
+    fn raw_with_preview(big_endian: bool, make: &str, preview_length: Option<u32>) -> Fixture {
+        let preview = preview_jpeg();
+        let mut make = make.as_bytes().to_vec();
+        make.push(0);
+
+        let make_offset = Fixture::data_offset(3);
+        let preview_offset = make_offset + make.len() as u32;
+        let mut fixture = Fixture::new(big_endian);
+        fixture.push_ifd(&[
+            (TAG_MAKE, TYPE_ASCII, make.len() as u32, make_offset),
+            (TAG_JPEG_OFFSET, TYPE_LONG, 1, preview_offset),
+            (TAG_JPEG_LENGTH, TYPE_LONG, 1, preview_length.unwrap_or(preview.len() as u32)),
+        ], 0);
+        fixture.data.extend_from_slice(&make);
+        fixture.data.extend_from_slice(&preview);
+        fixture
+    }
+
+    #[test]
+    fn reads_little_endian_files() {
+        let file = raw_with_preview(false, "NIKON CORPORATION", None).write();
+        assert_eq!(RawFormat::detect(&file.0).unwrap(), Some(RawFormat::Nef));
+        let preview = RawFormat::extract_preview(&file.0).unwrap().unwrap();
+        assert_eq!(preview.dimensions(), (3, 2));
+    }
+
+    #[test]
+    fn reads_big_endian_files() {
+        let file = raw_with_preview(true, "SONY", None).write();
+        assert_eq!(RawFormat::detect(&file.0).unwrap(), Some(RawFormat::Arw));
+        let preview = RawFormat::extract_preview(&file.0).unwrap().unwrap();
+        assert_eq!(preview.dimensions(), (3, 2));
+    }
+
+    #[test]
+    fn leaves_plain_tiff_files() {
+        let file = raw_with_preview(false, "Scanner", None).write();
+        assert_eq!(RawFormat::detect(&file.0).unwrap(), None);
+    }
+
+    #[test]
+    fn finds_no_missing_preview() {
+        // The RAW decoder is used instead.
+        let mut fixture = Fixture::new(false);
+        fixture.push_ifd(&[(TAG_COMPRESSION, TYPE_LONG, 1, 1)], 0);
+        let file = fixture.write();
+        assert!(RawFormat::extract_preview(&file.0).unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_oversized_previews() {
+        let file = raw_with_preview(false, "NIKON", Some(u32::MAX)).write();
+        assert!(RawFormat::extract_preview(&file.0).is_err());
+
+        let mut fixture = Fixture::new(true);
+        fixture.push_ifd(&[
+            (TAG_JPEG_OFFSET, TYPE_LONG, 1, u32::MAX - 16),
+            (TAG_JPEG_LENGTH, TYPE_LONG, 1, 1024),
+        ], 0);
+        let file = fixture.write();
+        assert!(RawFormat::extract_preview(&file.0).is_err());
+    }
+
+    #[test]
+    fn refuses_truncated_directories() {
+        let mut fixture = raw_with_preview(false, "NIKON", None);
+        fixture.data.truncate(Fixture::data_offset(3) as usize - 10);
+        let file = fixture.write();
+        assert!(RawFormat::detect(&file.0).is_err());
+        assert!(RawFormat::extract_preview(&file.0).is_err());
+
+        // Directory entries pointing past the end of the file.
+        let mut fixture = Fixture::new(true);
+        fixture.push_ifd(&[(TAG_MAKE, TYPE_ASCII, 64, 4096)], 0);
+        let file = fixture.write();
+        assert!(RawFormat::detect(&file.0).is_err());
+
+        let mut fixture = Fixture::new(false);
+        fixture.data.truncate(4);
+        fixture.push_u32(u32::MAX);
+        let file = fixture.write();
+        assert!(RawFormat::detect(&file.0).is_err());
+        assert!(RawFormat::extract_preview(&file.0).is_err());
+    }
+
+    #[test]
+    fn refuses_directory_loops() {
+        // The next directory is the first one again.
+        let mut fixture = Fixture::new(false);
+        fixture.push_ifd(&[(TAG_COMPRESSION, TYPE_LONG, 1, 1)], 8);
+        let file = fixture.write();
+        assert!(RawFormat::extract_preview(&file.0).is_err());
+
+        // A sub-directory refers to its parent.
+        let mut fixture = Fixture::new(true);
+        let sub_ifd_offset = Fixture::data_offset(1);
+        fixture.push_ifd(&[(TAG_SUB_IFDS, TYPE_LONG, 1, sub_ifd_offset)], 0);
+        fixture.push_ifd(&[(TAG_SUB_IFDS, TYPE_LONG, 1, 8)], 0);
+        let file = fixture.write();
+        assert!(RawFormat::extract_preview(&file.0).is_err());
+    }
+}
@@ -3,6 +3,26 @@ table! {
         id -> Text,
         name -> Text,
         parent_album_id -> Nullable<Text>,
+        access -> Text,
+        password_hash -> Nullable<Text>,
+        sort -> Nullable<Text>,
+    }
+}
+
+table! {
+    album_grants (album_id, principal) {
+        album_id -> Text,
+        principal -> Text,
+    }
+}
+
+table! {
+    job_errors (id) {
+        id -> Text,
+        job_id -> Text,
+        path -> Nullable<Text>,
+        message -> Text,
+        created_at -> Timestamp,
     }
 }
 
@@ -11,6 +31,15 @@ table! {
         id -> Text,
         name -> Text,
         state -> Text,
+        started_at -> Nullable<Timestamp>,
+        ended_at -> Nullable<Timestamp>,
+        files_seen -> Integer,
+        files_indexed -> Integer,
+        files_skipped -> Integer,
+        files_failed -> Integer,
+        current_directory -> Nullable<Text>,
+        failure_reason -> Nullable<Text>,
+        files_processed -> Integer,
     }
 }
 
@@ -27,11 +56,99 @@ table! {
         focal_length -> Nullable<Text>,
         focal_length_in_35mm -> Nullable<Text>,
         flash -> Nullable<Text>,
+        file_size -> BigInt,
+        file_mtime -> BigInt,
+        orientation -> Nullable<Integer>,
+        width -> Nullable<Integer>,
+        height -> Nullable<Integer>,
+        index_version -> Integer,
+        mime_type -> Nullable<Text>,
+        primary_photo_id -> Nullable<Text>,
+        duration -> Nullable<Double>,
+        caption -> Nullable<Text>,
+        keywords -> Nullable<Text>,
     }
 }
 
+table! {
+    photos_search (rowid) {
+        rowid -> BigInt,
+        name -> Text,
+        album_path -> Text,
+        caption -> Nullable<Text>,
+        keywords -> Nullable<Text>,
+    }
+}
+
+table! {
+    photos_search_keys (id) {
+        id -> BigInt,
+        photo_id -> Text,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Text,
+        user_id -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    settings (name) {
+        name -> Text,
+        value -> Text,
+    }
+}
+
+table! {
+    shares (id) {
+        id -> Text,
+        album_id -> Text,
+        photo_id -> Nullable<Text>,
+        label -> Text,
+        allow_download -> Bool,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    user_groups (user_id, group_name) {
+        user_id -> Text,
+        group_name -> Text,
+    }
+}
+
+table! {
+    users (id) {
+        id -> Text,
+        username -> Text,
+        password_hash -> Text,
+    }
+}
+
+joinable!(album_grants -> albums (album_id));
+joinable!(job_errors -> jobs (job_id));
+joinable!(sessions -> users (user_id));
+joinable!(shares -> albums (album_id));
+joinable!(shares -> photos (photo_id));
+joinable!(user_groups -> users (user_id));
+
 allow_tables_to_appear_in_same_query!(
+    album_grants,
     albums,
+    job_errors,
     jobs,
     photos,
+    photos_search,
+    photos_search_keys,
+    sessions,
+    settings,
+    shares,
+    user_groups,
+    users,
 );
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use askama::Template;
+use actix_web::actix::{Addr, Message};
+use chrono::{NaiveDate, NaiveDateTime};
+use futures::future::Future;
+
+use super::db::DbExecutor;
+use super::album::{Pagination, PageRequest, Viewer};
+use super::photo_thumbnail::{PhotoThumbnail, PhotoSort, ThumbnailSizes};
+use crate::error::GalleryError;
+use crate::utils;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Template)]
+#[template(path = "search.html")]
+pub struct SearchTemplate {
+    pub name: String,
+    pub breadcrumb: Vec<(String, String)>,
+    pub query: SearchQuery,
+    pub sort: PhotoSort,
+    pub results: Vec<SearchResult>,
+    pub total: i64,
+    pub filters: SearchFilters,
+    pub thumbnail_sizes: ThumbnailSizes,
+    pub pagination: Pagination,
+}
+
+/// Search asked for by the viewer, all the criteria given having to match.
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    /// Words looked for in the names, album paths, captions and keywords of
+    /// the photos.
+    pub text: String,
+    pub camera: Option<String>,
+    pub aperture: Option<String>,
+    pub focal_length: Option<String>,
+    /// First and last days the photos were taken, included.
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+pub struct SearchResult {
+    /// URL of the album of the photo, empty for the root album.
+    pub album_url: String,
+    pub photo: PhotoThumbnail,
+}
+
+/// Values of the EXIF columns offered as filters, taken from the photos the
+/// viewer can find.
+#[derive(Debug, Default, Serialize)]
+pub struct SearchFilters {
+    pub cameras: Vec<String>,
+    pub apertures: Vec<String>,
+    pub focal_lengths: Vec<String>,
+}
+
+pub struct SearchResults {
+    pub photos: Vec<SearchResult>,
+    pub total: i64,
+    pub filters: SearchFilters,
+}
+
+impl SearchQuery {
+    /// Reads the `q`, `camera`, `aperture`, `focal_length`, `from` and `to`
+    /// query parameters, ignoring empty and invalid values.
+    pub fn from_query(query: &HashMap<String, String>) -> Self {
+        let get_value = |name: &str| query.get(name).map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+        let get_date = |name: &str| get_value(name).and_then(|date| NaiveDate::parse_from_str(&date, DATE_FORMAT).ok());
+
+        SearchQuery {
+            text: get_value("q").unwrap_or_default(),
+            camera: get_value("camera"),
+            aperture: get_value("aperture"),
+            focal_length: get_value("focal_length"),
+            from: get_date("from"),
+            to: get_date("to"),
+        }
+    }
+
+    /// Full-text query matching the photos having every word of the text,
+    /// as a prefix so that the search can be typed partially. Operators of
+    /// the FTS5 syntax are not offered.
+    pub fn get_match_query(&self) -> Option<String> {
+        let words: Vec<String> = self.text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| format!("\"{}\"*", word))
+            .collect();
+        if words.is_empty() { None } else { Some(words.join(" ")) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.get_match_query().is_none() && self.camera.is_none() && self.aperture.is_none()
+            && self.focal_length.is_none() && self.from.is_none() && self.to.is_none()
+    }
+
+    /// Lower bound of the creation date of the photos.
+    pub fn get_date_from(&self) -> Option<NaiveDateTime> {
+        self.from.map(|from| from.and_hms(0, 0, 0))
+    }
+
+    /// Upper bound of the creation date of the photos, excluded.
+    pub fn get_date_until(&self) -> Option<NaiveDateTime> {
+        self.to.map(|to| to.succ().and_hms(0, 0, 0))
+    }
+
+    pub fn get_from_param(&self) -> String {
+        self.from.map(|from| from.format(DATE_FORMAT).to_string()).unwrap_or_default()
+    }
+
+    pub fn get_to_param(&self) -> String {
+        self.to.map(|to| to.format(DATE_FORMAT).to_string()).unwrap_or_default()
+    }
+
+    /// Query string of the search, the order of the results included when
+    /// given.
+    pub fn get_query(&self, sort: Option<PhotoSort>) -> String {
+        let params = [
+            ("q", Some(self.text.clone()).filter(|text| !text.is_empty())),
+            ("camera", self.camera.clone()),
+            ("aperture", self.aperture.clone()),
+            ("focal_length", self.focal_length.clone()),
+            ("from", self.from.map(|from| from.format(DATE_FORMAT).to_string())),
+            ("to", self.to.map(|to| to.format(DATE_FORMAT).to_string())),
+            ("sort", sort.map(|sort| sort.as_param().to_string())),
+        ];
+        let query: Vec<String> = params.iter()
+            .filter_map(|(name, value)| value.as_ref().map(|value| format!("{}={}", name, utils::encode_query_value(value))))
+            .collect();
+        if query.is_empty() { String::new() } else { format!("?{}", query.join("&")) }
+    }
+}
+
+impl SearchResult {
+    /// Path of the album relative to the root one.
+    pub fn get_album_path(&self) -> &str {
+        self.album_url.trim_start_matches('/')
+    }
+}
+
+impl SearchFilters {
+    /// Apertures and focal lengths are sorted by their value rather than as
+    /// text, "f/11" coming after "f/2.8".
+    pub fn new(cameras: Vec<String>, mut apertures: Vec<String>, mut focal_lengths: Vec<String>) -> Self {
+        let get_number = |value: &String| -> f64 {
+            value.trim_start_matches(|c: char| !c.is_ascii_digit())
+                .split(|c: char| !c.is_ascii_digit() && c != '.')
+                .next()
+                .and_then(|number| number.parse().ok())
+                .unwrap_or(0.0)
+        };
+        apertures.sort_by(|a, b| get_number(a).partial_cmp(&get_number(b)).unwrap_or_else(|| a.cmp(b)));
+        focal_lengths.sort_by(|a, b| get_number(a).partial_cmp(&get_number(b)).unwrap_or_else(|| a.cmp(b)));
+        SearchFilters { cameras, apertures, focal_lengths }
+    }
+}
+
+impl SearchTemplate {
+    /// Nothing is searched for until a criterion is given, the filters being
+    /// offered alone.
+    pub fn get(query: SearchQuery, page_request: PageRequest, gallery_name: String, thumbnail_sizes: ThumbnailSizes,
+        viewer: Viewer, db: Addr<DbExecutor>)
+        -> impl Future<Item = Self, Error = GalleryError>
+    {
+        let sort = page_request.sort.unwrap_or(PhotoSort::DateDesc);
+        db.send(SearchPhotos {
+            query: query.clone(),
+            sort,
+            offset: (page_request.page - 1).saturating_mul(page_request.per_page),
+            limit: page_request.per_page,
+            viewer,
+        })
+        .from_err::<GalleryError>()
+        .flatten()
+        .and_then(move |results| {
+            let pagination = Pagination::new(page_request.page, results.total, page_request.per_page, query.get_query(page_request.sort));
+            if pagination.page > pagination.page_count {
+                return Err(GalleryError::NotFound);
+            }
+
+            Ok(SearchTemplate {
+                name: "Search".to_string(),
+                breadcrumb: vec![("/".to_string(), gallery_name)],
+                query,
+                sort,
+                results: results.photos,
+                total: results.total,
+                filters: results.filters,
+                thumbnail_sizes,
+                pagination,
+            })
+        })
+    }
+
+    pub fn get_sorts(&self) -> &'static [PhotoSort] {
+        PhotoSort::ALL
+    }
+
+    pub fn get_photo_src(&self, result: &SearchResult) -> String {
+        let path = format!("{}/{}", result.album_url, result.photo.name);
+        self.thumbnail_sizes.get_default_url(&path, &result.photo.hash)
+    }
+
+    pub fn get_photo_srcset(&self, result: &SearchResult) -> String {
+        let path = format!("{}/{}", result.album_url, result.photo.name);
+        self.thumbnail_sizes.get_srcset(&path, &result.photo.hash, result.photo.width, result.photo.height)
+    }
+
+    /// Whether the option of a filter is the one searched for.
+    pub fn is_selected(&self, value: &str, selected: &Option<String>) -> bool {
+        selected.as_deref() == Some(value)
+    }
+}
+
+/// Photos matching the query in the albums the viewer can browse, stacked
+/// variants left out. Albums only opened by a share link are not searched.
+pub struct SearchPhotos {
+    pub query: SearchQuery,
+    pub sort: PhotoSort,
+    pub offset: i64,
+    pub limit: i64,
+    pub viewer: Viewer,
+}
+
+impl Message for SearchPhotos {
+    type Result = Result<SearchResults, GalleryError>;
+}
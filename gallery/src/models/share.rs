@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use actix_web::actix::Message;
+use askama::Template;
+use chrono::NaiveDateTime;
+
+use super::schema::shares;
+use crate::auth::AdminContext;
+use crate::error::GalleryError;
+use crate::utils::{hmac_sha256, constant_time_eq};
+
+/// Setting holding the key signing the share links.
+pub const SECRET_KEY_SETTING: &str = "share_secret_key";
+
+/// Link granting access to an album and its sub-albums, or to a single photo
+/// when `photo_id` is set.
+#[derive(Debug, Clone, Insertable, Queryable)]
+pub struct Share {
+    pub id: String,
+    /// Album shared, or holding the photo shared.
+    pub album_id: String,
+    pub photo_id: Option<String>,
+    pub label: String,
+    /// Whether the original files can be downloaded, videos being played
+    /// from them anyway.
+    pub allow_download: bool,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl Share {
+    /// Tokens are the id of the share followed by its signature, so that
+    /// they can't be forged from an id.
+    pub fn get_token(&self, secret_key: &[u8]) -> String {
+        format!("{}.{}", self.id, Self::sign(&self.id, secret_key))
+    }
+
+    /// Id of the share the token was made for, if its signature is valid.
+    pub fn verify_token<'a>(token: &'a str, secret_key: &[u8]) -> Option<&'a str> {
+        let (share_id, signature) = token.split_once('.')?;
+
+        if constant_time_eq(signature.as_bytes(), Self::sign(share_id, secret_key).as_bytes()) {
+            Some(share_id)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_active(&self, now: NaiveDateTime) -> bool {
+        self.revoked_at.is_none() && self.expires_at.map(|expires_at| expires_at > now).unwrap_or(true)
+    }
+
+    fn sign(share_id: &str, secret_key: &[u8]) -> String {
+        hmac_sha256(secret_key, share_id.as_bytes()).iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Share with the path of what it grants access to, for the admin pages.
+pub struct ShareListing {
+    pub share: Share,
+    pub path: String,
+    pub url: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/shares.html")]
+pub struct SharesTemplate {
+    pub admin: AdminContext,
+    pub new_share: Option<ShareListing>,
+    pub error: Option<String>,
+    pub shares: Vec<ShareListing>,
+}
+
+/// Shares the album or the photo at the path, relative to the storage.
+pub struct CreateShare {
+    pub path: PathBuf,
+    pub label: String,
+    pub allow_download: bool,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// Lists the shares which are neither revoked nor expired.
+pub struct GetActiveShares;
+
+/// Share of a token, if it is valid and still active.
+pub struct GetShareByToken {
+    pub token: String,
+}
+
+pub struct RevokeShare {
+    pub share_id: String,
+}
+
+impl Message for CreateShare {
+    type Result = Result<ShareListing, GalleryError>;
+}
+
+impl Message for GetActiveShares {
+    type Result = Result<Vec<ShareListing>, GalleryError>;
+}
+
+impl Message for GetShareByToken {
+    type Result = Result<Option<Share>, GalleryError>;
+}
+
+impl Message for RevokeShare {
+    type Result = Result<(), GalleryError>;
+}
@@ -0,0 +1,193 @@
+use std::{env, fs, io};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use image::{DynamicImage, GenericImageView, ImageDecoder, ImageFormat};
+
+use crate::config::DecodersConfig;
+use crate::error::GalleryError;
+use super::raw::RawFormat;
+use super::video::VideoFormat;
+
+/// Number of bytes read to recognize the format of a file.
+const HEADER_SIZE: usize = 16;
+
+/// Brands of the ISO base media files holding HEIF images.
+const HEIF_BRANDS: [&[u8]; 8] = [b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1"];
+
+/// Format of a source file, recognized from its content.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceFormat {
+    Image(ImageFormat),
+    Heif,
+    Raw(RawFormat),
+    Video(VideoFormat),
+}
+
+impl SourceFormat {
+    /// Reads the first bytes of the file, None is returned for files that
+    /// can't be decoded.
+    pub fn detect(path: &PathBuf) -> io::Result<Option<Self>> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        fs::File::open(path)?
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut header)?;
+
+        if header.len() >= 12 && &header[4..8] == b"ftyp" && HEIF_BRANDS.contains(&&header[8..12]) {
+            return Ok(Some(SourceFormat::Heif));
+        }
+        if let Some(video_format) = VideoFormat::detect(&header) {
+            return Ok(Some(SourceFormat::Video(video_format)));
+        }
+
+        // The signatures known by the image crate don't match these formats.
+        if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+            return match RawFormat::detect(path)? {
+                Some(raw_format) => Ok(Some(SourceFormat::Raw(raw_format))),
+                None => Ok(Some(SourceFormat::Image(ImageFormat::TIFF))),
+            };
+        }
+        if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WEBP" {
+            return Ok(Some(SourceFormat::Image(ImageFormat::WEBP)));
+        }
+
+        Ok(image::guess_format(&header).ok().map(SourceFormat::Image))
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            SourceFormat::Image(ImageFormat::PNG) => "image/png",
+            SourceFormat::Image(ImageFormat::JPEG) => "image/jpeg",
+            SourceFormat::Image(ImageFormat::GIF) => "image/gif",
+            SourceFormat::Image(ImageFormat::WEBP) => "image/webp",
+            SourceFormat::Image(ImageFormat::PNM) => "image/x-portable-anymap",
+            SourceFormat::Image(ImageFormat::TIFF) => "image/tiff",
+            SourceFormat::Image(ImageFormat::TGA) => "image/x-tga",
+            SourceFormat::Image(ImageFormat::BMP) => "image/bmp",
+            SourceFormat::Image(ImageFormat::ICO) => "image/x-icon",
+            SourceFormat::Image(ImageFormat::HDR) => "image/vnd.radiance",
+            SourceFormat::Heif => "image/heic",
+            SourceFormat::Raw(raw_format) => raw_format.mime_type(),
+            SourceFormat::Video(video_format) => video_format.mime_type(),
+        }
+    }
+
+    /// Decodes the image, or a frame of a video, turning it upright according
+    /// to its EXIF orientation. Images converted by external programs are
+    /// expected to be upright already.
+    pub fn decode(self, path: &PathBuf, orientation: Option<i32>, decoders: &DecodersConfig)
+        -> Result<DynamicImage, GalleryError>
+    {
+        match self {
+            // The decoder of the image crate only reads the luma of lossy
+            // WebP images, and not lossless ones.
+            SourceFormat::Image(ImageFormat::WEBP) => {
+                let command = decoders.webp.as_ref().ok_or(GalleryError::UnsupportedFormat)?;
+                Self::decode_with_command(command, |args, output_path| {
+                    args.arg(path).arg("-o").arg(output_path);
+                })
+            },
+            SourceFormat::Image(format) => {
+                let file = io::BufReader::new(fs::File::open(path)?);
+                Ok(apply_orientation(image::load(file, format)?, orientation))
+            },
+            SourceFormat::Heif => {
+                let command = decoders.heif.as_ref().ok_or(GalleryError::UnsupportedFormat)?;
+                Self::decode_with_command(command, |args, output_path| {
+                    args.arg(path).arg(output_path);
+                })
+            },
+            SourceFormat::Raw(_) => {
+                if let Some(preview) = RawFormat::extract_preview(path)? {
+                    return Ok(apply_orientation(preview, orientation));
+                }
+                let command = decoders.raw.as_ref()
+                    .ok_or_else(|| GalleryError::DecoderError("no embedded preview and no RAW decoder configured".to_string()))?;
+                Self::decode_with_command(command, |args, output_path| {
+                    args.arg(path).arg(output_path);
+                })
+            },
+            SourceFormat::Video(_) => {
+                let command = decoders.ffmpeg.as_ref().ok_or(GalleryError::UnsupportedFormat)?;
+                // The thumbnail filter picks a representative frame among the
+                // first ones, avoiding black frames of fade-ins.
+                Self::decode_with_command(command, |args, output_path| {
+                    args.args(["-v", "error", "-i"])
+                        .arg(path)
+                        .args(["-vf", "thumbnail", "-frames:v", "1"])
+                        .arg(output_path);
+                })
+            },
+        }
+    }
+
+    /// Reads the dimensions of an upright image from the header of the file
+    /// when possible. Other formats are decoded, except the ones needing an
+    /// external program for which None is returned.
+    pub fn read_dimensions(self, path: &PathBuf, orientation: Option<i32>, decoders: &DecodersConfig)
+        -> Result<Option<(u32, u32)>, GalleryError>
+    {
+        let format = match self {
+            SourceFormat::Image(format) if format != ImageFormat::WEBP => format,
+            _ => return Ok(None),
+        };
+
+        let file = io::BufReader::new(fs::File::open(path)?);
+        let (width, height) = match format {
+            ImageFormat::PNG => image::png::PNGDecoder::new(file)?.dimensions(),
+            ImageFormat::JPEG => image::jpeg::JPEGDecoder::new(file)?.dimensions(),
+            ImageFormat::GIF => image::gif::Decoder::new(file)?.dimensions(),
+            ImageFormat::TIFF => image::tiff::TIFFDecoder::new(file)?.dimensions(),
+            ImageFormat::BMP => image::bmp::BMPDecoder::new(file)?.dimensions(),
+            _ => return Ok(Some(self.decode(path, orientation, decoders)?.dimensions())),
+        };
+
+        let (width, height) = (width as u32, height as u32);
+        match orientation {
+            Some(5..=8) => Ok(Some((height, width))),
+            _ => Ok(Some((width, height))),
+        }
+    }
+
+    /// Converts the file to PNG with an external program, given the arguments
+    /// to pass along the destination path.
+    fn decode_with_command<F>(command: &str, build_args: F) -> Result<DynamicImage, GalleryError>
+    where
+        F: FnOnce(&mut Command, &Path),
+    {
+        let output_path = env::temp_dir().join(format!("gallery-{}.png", uuid::Uuid::new_v4()));
+
+        let mut args = Command::new(command);
+        build_args(&mut args, &output_path);
+        let output = args.output();
+
+        let result = match output {
+            Ok(ref output) if output.status.success() => image::open(&output_path).map_err(GalleryError::from),
+            Ok(output) => Err(GalleryError::DecoderError(format!(
+                "{} exited with {}, {}", command, output.status, String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+            Err(err) => Err(GalleryError::DecoderError(format!("could not run {}, {}", command, err))),
+        };
+
+        if output_path.exists() {
+            fs::remove_file(&output_path)?;
+        }
+        result
+    }
+}
+
+fn apply_orientation(img: DynamicImage, orientation: Option<i32>) -> DynamicImage {
+    // See the Orientation tag in the EXIF specification, values 5 to 8
+    // swap the width and the height.
+    match orientation {
+        Some(2) => img.fliph(),
+        Some(3) => img.rotate180(),
+        Some(4) => img.flipv(),
+        Some(5) => img.rotate90().fliph(),
+        Some(6) => img.rotate90(),
+        Some(7) => img.rotate270().fliph(),
+        Some(8) => img.rotate270(),
+        _ => img,
+    }
+}
@@ -0,0 +1,201 @@
+use askama::Template;
+use actix_web::actix::{Addr, Message};
+use chrono::{Datelike, NaiveDate};
+use futures::future::Future;
+
+use super::db::DbExecutor;
+use super::album::{Pagination, PageRequest, Viewer};
+use super::photo_thumbnail::ThumbnailSizes;
+use super::search::SearchResult;
+use crate::error::GalleryError;
+
+/// Photos of every album by the day they were taken, from the most recent.
+#[derive(Template)]
+#[template(path = "timeline.html")]
+pub struct TimelineTemplate {
+    pub name: String,
+    pub breadcrumb: Vec<(String, String)>,
+    pub years: Vec<TimelineYear>,
+    pub undated_count: i64,
+}
+
+#[derive(Template)]
+#[template(path = "timeline_day.html")]
+pub struct TimelineDayTemplate {
+    pub name: String,
+    pub breadcrumb: Vec<(String, String)>,
+    pub photos: Vec<SearchResult>,
+    pub total: i64,
+    pub previous_day: Option<TimelineDay>,
+    pub next_day: Option<TimelineDay>,
+    pub thumbnail_sizes: ThumbnailSizes,
+    pub pagination: Pagination,
+}
+
+/// Number of photos taken on a day, in the albums the viewer can browse.
+#[derive(Debug, Clone, Copy)]
+pub struct TimelineDay {
+    pub date: NaiveDate,
+    pub count: i64,
+}
+
+pub struct TimelineMonth {
+    /// First day of the month.
+    pub date: NaiveDate,
+    pub count: i64,
+    pub days: Vec<TimelineDay>,
+}
+
+pub struct TimelineYear {
+    pub year: i32,
+    pub count: i64,
+    pub months: Vec<TimelineMonth>,
+}
+
+pub struct Timeline {
+    /// From the oldest day.
+    pub days: Vec<TimelineDay>,
+    pub undated_count: i64,
+}
+
+pub struct TimelineDayResult {
+    pub photos: Vec<SearchResult>,
+    pub total: i64,
+    pub previous_day: Option<TimelineDay>,
+    pub next_day: Option<TimelineDay>,
+}
+
+impl TimelineDay {
+    pub fn get_url(&self) -> String {
+        format!("/timeline/{}", self.date.format("%Y/%m/%d"))
+    }
+
+    pub fn get_label(&self) -> String {
+        self.date.format("%a %-d").to_string()
+    }
+
+    pub fn get_name(&self) -> String {
+        self.date.format("%A %-d %B %Y").to_string()
+    }
+}
+
+impl TimelineMonth {
+    /// Anchor of the month on the timeline.
+    pub fn get_anchor(&self) -> String {
+        self.date.format("%Y-%m").to_string()
+    }
+
+    pub fn get_name(&self) -> String {
+        self.date.format("%B").to_string()
+    }
+
+    pub fn get_short_name(&self) -> String {
+        self.date.format("%b").to_string()
+    }
+}
+
+impl Timeline {
+    /// Groups the days by year and month, from the most recent.
+    pub fn get_years(self) -> Vec<TimelineYear> {
+        let mut years: Vec<TimelineYear> = Vec::new();
+        for day in self.days.into_iter().rev() {
+            if years.last().map(|year| year.year) != Some(day.date.year()) {
+                years.push(TimelineYear { year: day.date.year(), count: 0, months: Vec::new() });
+            }
+            let year = years.last_mut().unwrap();
+            if year.months.last().map(|month| month.date.month()) != Some(day.date.month()) {
+                year.months.push(TimelineMonth { date: day.date.with_day(1).unwrap(), count: 0, days: Vec::new() });
+            }
+            let month = year.months.last_mut().unwrap();
+            year.count += day.count;
+            month.count += day.count;
+            month.days.push(day);
+        }
+        years
+    }
+}
+
+impl TimelineTemplate {
+    pub fn get(gallery_name: String, viewer: Viewer, db: Addr<DbExecutor>) -> impl Future<Item = Self, Error = GalleryError> {
+        db.send(GetTimeline { viewer })
+            .from_err::<GalleryError>()
+            .flatten()
+            .map(move |timeline| TimelineTemplate {
+                name: "Timeline".to_string(),
+                breadcrumb: vec![("/".to_string(), gallery_name)],
+                undated_count: timeline.undated_count,
+                years: timeline.get_years(),
+            })
+    }
+}
+
+impl TimelineDayTemplate {
+    /// Days without photos the viewer can browse are not found.
+    pub fn get(date: NaiveDate, page_request: PageRequest, gallery_name: String, thumbnail_sizes: ThumbnailSizes,
+        viewer: Viewer, db: Addr<DbExecutor>)
+        -> impl Future<Item = Self, Error = GalleryError>
+    {
+        db.send(GetTimelineDay {
+            date,
+            offset: (page_request.page - 1).saturating_mul(page_request.per_page),
+            limit: page_request.per_page,
+            viewer,
+        })
+        .from_err::<GalleryError>()
+        .flatten()
+        .and_then(move |result| {
+            let pagination = Pagination::new(page_request.page, result.total, page_request.per_page, String::new());
+            if result.total == 0 || pagination.page > pagination.page_count {
+                return Err(GalleryError::NotFound);
+            }
+
+            Ok(TimelineDayTemplate {
+                name: TimelineDay { date, count: result.total }.get_name(),
+                breadcrumb: vec![
+                    ("/".to_string(), gallery_name),
+                    ("/timeline".to_string(), "Timeline".to_string()),
+                    (format!("/timeline#{}", date.format("%Y-%m")), date.format("%B %Y").to_string()),
+                ],
+                photos: result.photos,
+                total: result.total,
+                previous_day: result.previous_day,
+                next_day: result.next_day,
+                thumbnail_sizes,
+                pagination,
+            })
+        })
+    }
+
+    pub fn get_photo_src(&self, result: &SearchResult) -> String {
+        let path = format!("{}/{}", result.album_url, result.photo.name);
+        self.thumbnail_sizes.get_default_url(&path, &result.photo.hash)
+    }
+
+    pub fn get_photo_srcset(&self, result: &SearchResult) -> String {
+        let path = format!("{}/{}", result.album_url, result.photo.name);
+        self.thumbnail_sizes.get_srcset(&path, &result.photo.hash, result.photo.width, result.photo.height)
+    }
+}
+
+/// Days the photos of the albums the viewer can browse were taken, stacked
+/// variants left out.
+pub struct GetTimeline {
+    pub viewer: Viewer,
+}
+
+impl Message for GetTimeline {
+    type Result = Result<Timeline, GalleryError>;
+}
+
+/// Photos taken on the day, in the order they were taken, with the days
+/// before and after having photos.
+pub struct GetTimelineDay {
+    pub date: NaiveDate,
+    pub offset: i64,
+    pub limit: i64,
+    pub viewer: Viewer,
+}
+
+impl Message for GetTimelineDay {
+    type Result = Result<TimelineDayResult, GalleryError>;
+}
@@ -0,0 +1,203 @@
+use actix_web::actix::{Actor, Addr, Message, SyncArbiter, SyncContext};
+use askama::Template;
+use chrono::NaiveDateTime;
+use futures::future::Future;
+use rand::Rng;
+use sha2::{Sha256, Digest};
+
+use super::db::DbExecutor;
+use super::schema::{users, sessions, user_groups};
+use crate::error::{GalleryError, GalleryInternalError};
+
+#[derive(Debug, Clone, Insertable, Queryable)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub password_hash: String,
+}
+
+impl User {
+    pub fn hash_password(password: &str) -> Result<String, GalleryError> {
+        let salt: [u8; 16] = rand::thread_rng().gen();
+        argon2::hash_encoded(password.as_bytes(), &salt, &argon2::Config::default())
+            .map_err(GalleryInternalError)
+    }
+
+    pub fn verify_password(password_hash: &str, password: &str) -> bool {
+        argon2::verify_encoded(password_hash, password.as_bytes()).unwrap_or(false)
+    }
+}
+
+/// Number of threads hashing the passwords.
+const PASSWORD_WORKERS: usize = 2;
+
+/// Hashes and verifies the passwords, which is slow on purpose, away from the
+/// database so that logging in doesn't hold up the other queries.
+pub struct PasswordHasher;
+
+impl Actor for PasswordHasher {
+    type Context = SyncContext<Self>;
+}
+
+impl PasswordHasher {
+    pub fn init() -> Addr<Self> {
+        SyncArbiter::start(PASSWORD_WORKERS, || PasswordHasher)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "user_groups"]
+pub struct UserGroup {
+    pub user_id: String,
+    pub group_name: String,
+}
+
+/// Members of this group can access the admin pages and all the albums.
+pub const ADMIN_GROUP: &str = "admin";
+
+/// User on whose behalf a request is made.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub username: String,
+    pub groups: Vec<String>,
+    /// Token of the session, missing when the user is authenticated by the
+    /// reverse proxy.
+    pub session_token: Option<String>,
+}
+
+impl Identity {
+    pub fn is_admin(&self) -> bool {
+        self.groups.iter().any(|group| group == ADMIN_GROUP)
+    }
+
+    /// Principals are either user names, or group names prefixed with "@".
+    pub fn is_granted(&self, principals: &[String]) -> bool {
+        principals.iter().any(|principal| match principal.strip_prefix('@') {
+            Some(group_name) => self.groups.iter().any(|group| group == group_name),
+            None => principal == &self.username,
+        })
+    }
+}
+
+#[derive(Insertable, Queryable)]
+pub struct Session {
+    /// Hash of the token stored in the cookie of the browser, so that the
+    /// content of the database can't be used to log in.
+    pub id: String,
+    pub user_id: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+impl Session {
+    pub fn generate_token() -> String {
+        let bytes: [u8; 32] = rand::thread_rng().gen();
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn hash_token(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/login.html")]
+pub struct LoginTemplate {
+    pub csrf_token: String,
+    pub error: Option<String>,
+}
+
+/// Creates the user or replaces their password, logging them out.
+pub struct SetPassword {
+    pub username: String,
+    pub password: String,
+    /// Replaces the groups of the user when set.
+    pub groups: Option<Vec<String>>,
+}
+
+impl Message for SetPassword {
+    type Result = Result<(), GalleryError>;
+}
+
+pub struct Login {
+    pub username: String,
+    pub password: String,
+    pub session_days: i64,
+}
+
+impl Login {
+    /// Checks the credentials and opens a session for the user, returning
+    /// their identity with its token.
+    pub fn open_session(self, db: Addr<DbExecutor>, hasher: Addr<PasswordHasher>) -> impl Future<Item = Identity, Error = GalleryError> {
+        let Login { username, password, session_days } = self;
+        let session_db = db.clone();
+
+        db.send(GetUser { username })
+            .from_err::<GalleryError>()
+            .flatten()
+            .and_then(move |user| {
+                let password_hash = user.as_ref().map(|user| user.password_hash.clone());
+                hasher.send(VerifyPassword { password, password_hash })
+                    .from_err::<GalleryError>()
+                    .flatten()
+                    .and_then(move |verified| match user {
+                        Some(user) if verified => Ok(user),
+                        _ => Err(GalleryError::InvalidCredentials),
+                    })
+            })
+            .and_then(move |user| {
+                session_db.send(OpenSession { user, session_days })
+                    .from_err::<GalleryError>()
+                    .flatten()
+            })
+    }
+}
+
+pub struct GetUser {
+    pub username: String,
+}
+
+impl Message for GetUser {
+    type Result = Result<Option<User>, GalleryError>;
+}
+
+/// Checks a password against its hash. Without a hash, the user not
+/// existing, the password is hashed all the same to take as long, not to
+/// disclose which names are taken.
+pub struct VerifyPassword {
+    pub password: String,
+    pub password_hash: Option<String>,
+}
+
+impl Message for VerifyPassword {
+    type Result = Result<bool, GalleryError>;
+}
+
+/// Opens a session for a user whose credentials were checked.
+pub struct OpenSession {
+    pub user: User,
+    pub session_days: i64,
+}
+
+impl Message for OpenSession {
+    type Result = Result<Identity, GalleryError>;
+}
+
+pub struct Logout {
+    pub token: String,
+}
+
+impl Message for Logout {
+    type Result = Result<(), GalleryError>;
+}
+
+/// Finds the user of a session which has not expired, or the one named by
+/// the reverse proxy.
+pub struct GetIdentity {
+    pub session_token: Option<String>,
+    pub proxy_username: Option<String>,
+}
+
+impl Message for GetIdentity {
+    type Result = Result<Option<Identity>, GalleryError>;
+}
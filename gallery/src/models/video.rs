@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use serde_json::Value;
+
+use crate::config::DecodersConfig;
+use crate::error::GalleryError;
+
+/// Brands of the ISO base media files holding videos.
+const VIDEO_BRANDS: [&[u8]; 13] = [
+    b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"3gp4", b"3gp5", b"3g2a", b"qt  ",
+];
+
+/// First atoms of QuickTime files written before the ftyp atom existed.
+const QUICKTIME_ATOMS: [&[u8]; 3] = [b"moov", b"mdat", b"wide"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoFormat {
+    Mp4,
+    QuickTime,
+}
+
+impl VideoFormat {
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.len() < 12 {
+            return None;
+        }
+
+        let (atom, brand) = (&header[4..8], &header[8..12]);
+        if atom == b"ftyp" && VIDEO_BRANDS.contains(&brand) {
+            if brand == b"qt  " {
+                Some(VideoFormat::QuickTime)
+            } else {
+                Some(VideoFormat::Mp4)
+            }
+        } else if QUICKTIME_ATOMS.contains(&atom) {
+            Some(VideoFormat::QuickTime)
+        } else {
+            None
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "video/mp4",
+            VideoFormat::QuickTime => "video/quicktime",
+        }
+    }
+}
+
+pub struct VideoMetadata {
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+    /// Local time of the recording, like the EXIF dates.
+    pub creation_date: Option<NaiveDateTime>,
+    /// Dimensions of the frames once rotated for display.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+impl VideoMetadata {
+    pub fn probe(path: &PathBuf, decoders: &DecodersConfig) -> Result<Self, GalleryError> {
+        let command = decoders.ffprobe.as_ref().ok_or(GalleryError::UnsupportedFormat)?;
+
+        let output = Command::new(command)
+            .args(["-v", "error", "-print_format", "json", "-show_format", "-show_streams"])
+            .arg(path)
+            .output()
+            .map_err(|err| GalleryError::DecoderError(format!("could not run {}, {}", command, err)))?;
+
+        if !output.status.success() {
+            return Err(GalleryError::DecoderError(format!(
+                "{} exited with {}, {}", command, output.status, String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let probe: Value = serde_json::from_slice(&output.stdout)
+            .map_err(|err| GalleryError::DecoderError(format!("invalid output of {}, {}", command, err)))?;
+
+        let duration = probe["format"]["duration"].as_str()
+            .and_then(|duration| duration.parse().ok());
+
+        let video_stream = probe["streams"].as_array()
+            .and_then(|streams| streams.iter().find(|stream| stream["codec_type"] == "video"));
+
+        // The container and the streams are dated in UTC.
+        let creation_date = probe["format"]["tags"]["creation_time"].as_str()
+            .or_else(|| video_stream.and_then(|stream| stream["tags"]["creation_time"].as_str()))
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| date.with_timezone(&Local).naive_local());
+
+        let dimensions = video_stream.and_then(|stream| {
+            Some((stream["width"].as_i64()? as i32, stream["height"].as_i64()? as i32))
+        });
+        // The rotation is a tag in older versions of ffprobe, and side data
+        // of the stream in newer ones.
+        let rotation = video_stream.and_then(|stream| {
+            stream["tags"]["rotate"].as_str().and_then(|rotation| rotation.parse::<i64>().ok())
+                .or_else(|| stream["side_data_list"].as_array()?.iter().find_map(|data| data["rotation"].as_i64()))
+        });
+        let (width, height) = match (dimensions, rotation) {
+            (Some((width, height)), Some(rotation)) if rotation.abs() % 180 == 90 => (Some(height), Some(width)),
+            (Some((width, height)), _) => (Some(width), Some(height)),
+            (None, _) => (None, None),
+        };
+
+        Ok(VideoMetadata { duration, creation_date, width, height })
+    }
+}
@@ -1,123 +1,775 @@
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use askama::Template;
-use actix_web::{HttpRequest, HttpResponse, Result, Either, fs::NamedFile, AsyncResponder, State, HttpMessage};
+use actix_web::{HttpRequest, HttpResponse, Result, AsyncResponder, State, HttpMessage, Responder};
+use actix_web::fs::{NamedFile, StaticFileConfig};
+use actix_web::http::{header, StatusCode};
 use futures::future::{self, Future};
+use sha2::{Sha256, Digest};
+use chrono::{Duration, NaiveDate, Utc};
 
 use crate::utils::*;
 use crate::models::{Album, AlbumTemplate, Photo, PhotoTemplate, PhotoThumbnail};
-use crate::models::job::{GetJobs, CreateJob, JobsTemplate};
+use crate::models::photo_thumbnail::ThumbnailSizes;
+use crate::models::job::{GetJobs, GetJob, GetJobErrors, CreateJob, CancelJob, JobsTemplate, JobTemplate};
 use crate::error::{GalleryError, GalleryInternalError};
-use crate::common::AppState;
-use crate::indexer::walker_actor::StartWalking;
+use crate::common::{AppState, JobRunners};
+use crate::indexer::renderer_actor::RenderThumbnail;
+use crate::models::user::{Login, Logout, LoginTemplate};
+use crate::models::user::Identity;
+use crate::models::album::{PageRequest, UnlockAlbum, UnlockTemplate};
+use crate::models::search::{SearchQuery, SearchTemplate};
+use crate::models::timeline::{TimelineTemplate, TimelineDayTemplate};
+use crate::models::share::{ShareListing, SharesTemplate, CreateShare, GetActiveShares, GetShareByToken, RevokeShare};
+use crate::auth::{
+    AdminContext, SESSION_COOKIE, ALBUM_COOKIE_PREFIX, SHARE_COOKIE_PREFIX, LOGIN_PATH, build_cookie, check_csrf,
+    get_csrf_token, get_viewer, get_share_query_token, get_client_ip
+};
 
 
 pub fn gallery_route((req, state): (HttpRequest<AppState>, State<AppState>))
-    -> Box<Future<Item = Either<AlbumTemplate, PhotoTemplate>, Error = GalleryError>>
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
 {
     let path: PathBuf = future_try!(req.match_info().query("path").map_err(GalleryInternalError));
-    AlbumTemplate::get(path.clone(), state.db.clone())
-        .map(|album| Either::A(album))
-        .or_else(move |err| -> Box<Future<Item = Either<AlbumTemplate, PhotoTemplate>, Error = GalleryError>> {
+    // Album cards are cropped to squares, photos are displayed whole.
+    let album_sizes = ThumbnailSizes::new(&state.config, true);
+    let photo_sizes = ThumbnailSizes::new(&state.config, false);
+    let page_request = PageRequest::from_query(&req.query(), state.config.albums.photos_per_page);
+    let (album_path, db_addr, cookie_req, unlock_req) = (path.clone(), state.db.clone(), req.clone(), req.clone());
+    get_viewer(&req)
+        .and_then(move |viewer| AlbumTemplate::get(album_path, page_request, album_sizes, viewer, db_addr))
+        .and_then(move |album| {
+            let body = album.render().map_err(GalleryInternalError)?;
+            let validators = CacheValidators {
+                etag: header::EntityTag::weak(get_body_digest(&body)),
+                last_modified: None,
+                cache_control: if album.is_restricted { "private, no-cache" } else { "public, no-cache" },
+            };
+            validators.respond(&req, || Ok(HttpResponse::Ok().content_type("text/html").body(body)))
+        })
+        .or_else(move |err| -> Box<Future<Item = HttpResponse, Error = GalleryError>> {
             match err {
                  GalleryError::AlbumNotFound {
                      missing_segments,
                      ref last_album,
                      ref current_breadcrumb,
+                     grant,
                  } if missing_segments == 1 => {
                     let name = future_try!(get_file_name_string(path));
 
                     let res = PhotoTemplate::get(name,
                         last_album.to_owned(),
                         current_breadcrumb.clone(),
+                        page_request.sort,
+                        photo_sizes,
+                        grant,
                         state.db.clone(),
-                    ).map(|photo| Either::B(photo));
+                    ).and_then(|photo| {
+                        let body = photo.render().map_err(GalleryInternalError)?;
+                        Ok(HttpResponse::Ok().content_type("text/html").body(body))
+                    });
                     Box::new(res)
                 },
+                GalleryError::AlbumLocked { album_id, name } => {
+                    Box::new(future::result(render_locked_album(&unlock_req, album_id, name, None)))
+                },
                 e => Box::new(future::err(e))
             }
-        }).responder()
+        })
+        .and_then(move |mut res| -> Box<Future<Item = HttpResponse, Error = GalleryError>> {
+            // Remembers the share link opened, for the thumbnails and the
+            // pages linked, no longer than the link is valid.
+            let share_token = match get_share_query_token(&cookie_req) {
+                Some(share_token) if res.status().is_success() => share_token,
+                _ => return Box::new(future::ok(res)),
+            };
+            Box::new(cookie_req.state().db.send(GetShareByToken { token: share_token.clone() })
+                .from_err::<GalleryError>()
+                .flatten()
+                .map(move |share| {
+                    if let Some(share) = share {
+                        let auth_config = &cookie_req.state().config.auth;
+                        let mut max_age = time::Duration::days(auth_config.session_days);
+                        if let Some(expires_at) = share.expires_at {
+                            let remaining = expires_at.signed_duration_since(Utc::now().naive_utc()).num_seconds();
+                            max_age = max_age.min(time::Duration::seconds(remaining.max(0)));
+                        }
+                        let mut cookie = build_cookie(format!("{}{}", SHARE_COOKIE_PREFIX, share.id), share_token, "/", auth_config);
+                        cookie.set_max_age(max_age);
+                        let _ = res.add_cookie(&cookie);
+                    }
+                    res
+                }))
+        })
+        .responder()
+}
+
+/// The results depend on the viewer, they are never stored by shared caches.
+pub fn search_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let query = SearchQuery::from_query(&req.query());
+    let page_request = PageRequest::from_query(&req.query(), state.config.albums.photos_per_page);
+    let sizes = ThumbnailSizes::new(&state.config, true);
+    let (gallery_name, db_addr) = (state.config.gallery_name.clone(), state.db.clone());
+
+    get_viewer(&req)
+        .and_then(move |viewer| SearchTemplate::get(query, page_request, gallery_name, sizes, viewer, db_addr))
+        .and_then(|search| {
+            let body = search.render().map_err(GalleryInternalError)?;
+            Ok(HttpResponse::Ok()
+                .content_type("text/html")
+                .header(header::CACHE_CONTROL, "private, no-cache")
+                .body(body))
+        })
+        .responder()
+}
+
+pub fn timeline_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let (gallery_name, db_addr) = (state.config.gallery_name.clone(), state.db.clone());
+
+    get_viewer(&req)
+        .and_then(move |viewer| TimelineTemplate::get(gallery_name, viewer, db_addr))
+        .and_then(|timeline| {
+            let body = timeline.render().map_err(GalleryInternalError)?;
+            Ok(HttpResponse::Ok()
+                .content_type("text/html")
+                .header(header::CACHE_CONTROL, "private, no-cache")
+                .body(body))
+        })
+        .responder()
+}
+
+pub fn timeline_day_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let date = future_try!(get_timeline_date(&req).ok_or(GalleryError::NotFound));
+    let page_request = PageRequest::from_query(&req.query(), state.config.albums.photos_per_page);
+    let sizes = ThumbnailSizes::new(&state.config, true);
+    let (gallery_name, db_addr) = (state.config.gallery_name.clone(), state.db.clone());
+
+    get_viewer(&req)
+        .and_then(move |viewer| TimelineDayTemplate::get(date, page_request, gallery_name, sizes, viewer, db_addr))
+        .and_then(|day| {
+            let body = day.render().map_err(GalleryInternalError)?;
+            Ok(HttpResponse::Ok()
+                .content_type("text/html")
+                .header(header::CACHE_CONTROL, "private, no-cache")
+                .body(body))
+        })
+        .responder()
+}
+
+fn get_timeline_date(req: &HttpRequest<AppState>) -> Option<NaiveDate> {
+    let year = req.match_info().query::<i32>("year").ok()?;
+    let month = req.match_info().query::<u32>("month").ok()?;
+    let day = req.match_info().query::<u32>("day").ok()?;
+    NaiveDate::from_ymd_opt(year, month, day)
 }
 
 pub fn thumbnail_route((req, state): (HttpRequest<AppState>, State<AppState>))
-    -> Box<Future<Item = NamedFile, Error = GalleryError>>
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
 {
-    let path: PathBuf = future_try!(req.match_info().query("path").map_err(GalleryInternalError));
+    // Segments refused by the router, such as hidden files, can't be photos.
+    let path: PathBuf = future_try!(req.match_info().query("path").map_err(|_| GalleryError::NotFound));
     let thumbnail_size: String = future_try!(req.match_info().query("thumbnail_size").map_err(GalleryInternalError));
 
     let name = future_try!(get_file_name_string(&path).map_err(GalleryError::from));
 
     let thumbnail_config = future_try!(state.config.thumbnails.get(&thumbnail_size).ok_or(GalleryError::NotFound)).clone();
 
-    let thumbnail_path = future_try!(path.parent().ok_or(GalleryError::NotFound)).to_path_buf();
+    let album_path = future_try!(path.parent().ok_or(GalleryError::NotFound)).to_path_buf();
     let cache_path = state.config.cache_path.clone();
+    let cache_version_path = cache_path.clone();
+    let storage_path = state.config.storage_path.clone();
+    let renderer = state.renderer.clone();
+    let render_config = thumbnail_config.clone();
+    let (viewer_album_path, db_addr) = (album_path.clone(), state.db.clone());
 
-    Album::get(thumbnail_path, state.db.clone())
+    let photo_name = name.clone();
+
+    get_viewer(&req)
+        .and_then(move |viewer| Album::get(viewer_album_path, Some(photo_name), viewer, db_addr))
         .and_then(move |result| {
+            let is_restricted = result.is_restricted;
             Photo::get(name, result.album.id, state.db.clone())
+                .map(move |photo| (photo, is_restricted))
         })
-        .and_then(move |photo| -> Box<Future<Item = NamedFile, Error = GalleryError>> {
-            let res = NamedFile::open(PhotoThumbnail::get_image_path(
-                &photo.hash,
-                &thumbnail_config,
-                cache_path
-            ));
-            match res {
-                Ok(res) => Box::new(future::result(Ok(res))),
-                Err(e) => Box::new(future::err(GalleryError::from(e)))
+        .and_then(move |(photo, is_restricted)| -> Box<Future<Item = (String, PathBuf, bool), Error = GalleryError>> {
+            let thumbnail_path = PhotoThumbnail::get_image_path(&photo.hash, &render_config, cache_path);
+            // Thumbnails are only moved in place once complete.
+            match renderer {
+                Some(ref renderer) if !thumbnail_path.is_file() => {
+                    let source_path = future_try!(resolve_storage_path(Path::new(&storage_path), &album_path.join(&photo.name)));
+                    let hash = photo.hash.clone();
+                    Box::new(renderer.send(RenderThumbnail {
+                        path: source_path,
+                        hash: photo.hash,
+                        orientation: photo.orientation,
+                        thumbnail_config: render_config,
+                    }).from_err::<GalleryError>().flatten().map(move |thumbnail_path| (hash, thumbnail_path, is_restricted)))
+                },
+                _ => Box::new(future::ok((photo.hash, thumbnail_path, is_restricted))),
             }
         })
+        .and_then(move |(hash, thumbnail_path, is_restricted)| -> Result<HttpResponse, GalleryError> {
+            let accept = req.headers().get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .unwrap_or_default();
+
+            // Fall back on the main format when the preferred ones were not
+            // generated, for instance before the configuration changed.
+            let format = PhotoThumbnail::get_accepted_formats(accept, &thumbnail_config.formats).into_iter()
+                .find(|&format| PhotoThumbnail::get_format_path(&thumbnail_path, format).is_file());
+
+            let file_path = match format {
+                Some(format) => PhotoThumbnail::get_format_path(&thumbnail_path, format),
+                None => thumbnail_path,
+            };
+
+            // The version follows the photo, the configuration of the
+            // thumbnail and its generation, thumbnails generated again
+            // getting new URLs in the pages.
+            let version = PhotoThumbnail::get_version(&hash, &thumbnail_config, cache_version_path);
+            let versioned = req.query().get("v")
+                .map(|requested_version| *requested_version == version)
+                .unwrap_or(false);
+            let extension = file_path.extension().map(|extension| extension.to_string_lossy().to_string()).unwrap_or_default();
+            let validators = CacheValidators {
+                etag: header::EntityTag::strong(format!("{}.{}", version, extension)),
+                last_modified: fs::metadata(&file_path)?.modified().ok(),
+                cache_control: match (versioned, is_restricted) {
+                    (true, false) => "public, max-age=31536000, immutable",
+                    (true, true) => "private, max-age=31536000, immutable",
+                    (false, false) => "public, no-cache",
+                    (false, true) => "private, no-cache",
+                },
+            };
+
+            let mut res = validators.respond(&req, || {
+                let mut res = NamedFile::open_with_config(&file_path, ValidatedFile)?.respond_to(&req)?;
+                if let Some(format) = format {
+                    res.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static(format.mime_type()));
+                }
+                Ok(res)
+            })?;
+
+            if !thumbnail_config.formats.is_empty() {
+                res.headers_mut().insert(header::VARY, header::HeaderValue::from_static("accept"));
+            }
+            Ok(res)
+        })
         .responder()
 }
 
-pub fn full_photo_route(req: &HttpRequest<AppState>) -> Result<NamedFile> {
-    let state = req.state();
-    let path = get_album_canonical_path(req.match_info().query("path")?, &state.config);
+pub fn full_photo_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    // Segments refused by the router, such as hidden files, can't be photos.
+    let path: PathBuf = future_try!(req.match_info().query("path").map_err(|_| GalleryError::NotFound));
+    let name = future_try!(get_file_name_string(&path));
+    let album_path = future_try!(path.parent().ok_or(GalleryError::NotFound)).to_path_buf();
+    let storage_path = state.config.storage_path.clone();
+    let (viewer_album_path, db_addr) = (album_path.clone(), state.db.clone());
+
+    let photo_name = name.clone();
 
-    Ok(NamedFile::open(path)?)
+    get_viewer(&req)
+        .and_then(move |viewer| Album::get(viewer_album_path, Some(photo_name), viewer, db_addr))
+        .and_then(move |result| {
+            let (is_restricted, grant) = (result.is_restricted, result.grant);
+            Photo::get(name, result.album.id, state.db.clone())
+                .map(move |photo| (photo, is_restricted, grant))
+        })
+        .and_then(move |(photo, is_restricted, grant)| {
+            // Videos are played from their file, which can't be refused to
+            // the viewers allowed to watch them.
+            if !grant.allows_download() && !photo.is_video() {
+                return Err(GalleryError::NotFound);
+            }
+            // Only indexed photos are served, from inside the storage.
+            let source_path = resolve_storage_path(Path::new(&storage_path), &album_path.join(&photo.name))?;
+            let validators = CacheValidators {
+                etag: header::EntityTag::strong(photo.hash.clone()),
+                last_modified: fs::metadata(&source_path)?.modified().ok(),
+                cache_control: if is_restricted { "private, no-cache" } else { "public, no-cache" },
+            };
+
+            validators.respond(&req, || {
+                let file = NamedFile::open_with_config(&source_path, ValidatedFile)?;
+                // Browsers can't display RAW files, send them as a download instead.
+                let file = if photo.is_raw() {
+                    file.set_content_disposition(header::ContentDisposition {
+                        disposition: header::DispositionType::Attachment,
+                        parameters: vec![header::DispositionParam::Filename(photo.name.clone())],
+                    })
+                } else {
+                    file
+                };
+                Ok(file.respond_to(&req)?)
+            })
+        })
+        .responder()
+}
+
+#[derive(Deserialize)]
+struct UnlockForm {
+    album_id: String,
+    password: String,
+    #[serde(default)]
+    csrf_token: String,
+}
+
+/// Checks the password of a protected album, sent from the page of the
+/// album or of one of its descendants.
+pub fn unlock_album_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let form_req = req.clone();
+    let session_days = state.config.auth.session_days;
+
+    req.urlencoded::<UnlockForm>()
+        .map_err(|e| GalleryError::ActixError(e.into()))
+        .and_then(move |form| {
+            check_csrf(&form_req, &form.csrf_token)?;
+            Ok(form)
+        })
+        .and_then(move |form| {
+            let album_id = form.album_id.clone();
+            UnlockAlbum { album_id: form.album_id, password: form.password }
+                .unlock(state.db.clone(), state.hasher.clone())
+                .map(|token| (album_id, token))
+        })
+        .then(move |res| {
+            match res {
+                Ok((album_id, token)) => {
+                    let cookie_name = format!("{}{}", ALBUM_COOKIE_PREFIX, album_id);
+                    let mut cookie = build_cookie(cookie_name, token, "/", &req.state().config.auth);
+                    cookie.set_max_age(time::Duration::days(session_days));
+                    Ok(HttpResponse::SeeOther()
+                        .header(header::LOCATION, req.path())
+                        .cookie(cookie)
+                        .finish())
+                },
+                Err(GalleryError::AlbumLocked { album_id, name }) => {
+                    render_locked_album(&req, album_id, name, Some(GalleryError::InvalidCredentials.to_string()))
+                },
+                Err(e) => Err(e),
+            }
+        })
+        .responder()
 }
 
-pub fn get_jobs_route((_req, state): (HttpRequest<AppState>, State<AppState>)) -> impl Future<Item = JobsTemplate, Error = GalleryError> {
+fn render_locked_album(req: &HttpRequest<AppState>, album_id: String, name: String, error: Option<String>)
+    -> Result<HttpResponse, GalleryError>
+{
+    let body = UnlockTemplate {
+        name,
+        breadcrumb: Vec::new(),
+        album_id,
+        csrf_token: get_csrf_token(req),
+        error,
+    }.render().map_err(GalleryInternalError)?;
+    Ok(HttpResponse::Unauthorized()
+        .content_type("text/html")
+        .header(header::CACHE_CONTROL, "private, no-cache")
+        .body(body))
+}
+
+pub fn get_jobs_route((req, state): (HttpRequest<AppState>, State<AppState>)) -> impl Future<Item = JobsTemplate, Error = GalleryError> {
     state.db.send(GetJobs).from_err::<GalleryError>()
         .flatten()
-        .and_then(|jobs| {
+        .and_then(move |jobs| {
             Ok(JobsTemplate {
+                admin: AdminContext::from_request(&req),
                 new_job_id: None,
+                error: None,
                 jobs
             })
         })
 }
 
+pub fn get_job_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = JobTemplate, Error = GalleryError>>
+{
+    let job_id: String = future_try!(req.match_info().query("job_id").map_err(GalleryInternalError));
+
+    let job_future = state.db.send(GetJob { job_id: job_id.clone() })
+        .from_err::<GalleryError>()
+        .flatten();
+    let errors_future = state.db.send(GetJobErrors { job_id })
+        .from_err::<GalleryError>()
+        .flatten();
+
+    job_future
+        .join(errors_future)
+        .map(move |(job, errors)| JobTemplate { admin: AdminContext::from_request(&req), job, errors })
+        .responder()
+}
+
+#[derive(Deserialize)]
+struct JobForm {
+    name: String,
+    #[serde(default)]
+    csrf_token: String,
+}
+
+#[derive(Deserialize)]
+struct CsrfForm {
+    #[serde(default)]
+    csrf_token: String,
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+    #[serde(default)]
+    csrf_token: String,
+}
+
 pub fn post_jobs_route((req, state): (HttpRequest<AppState>, State<AppState>)) -> Box<Future<Item = HttpResponse, Error = GalleryError>> {
-    let walker_addr = state.walker.clone();
+    let job_runners = state.jobs.clone();
     let db_addr = state.db.clone();
+    let form_req = req.clone();
 
-    req.urlencoded::<CreateJob>()
+    req.urlencoded::<JobForm>()
         .map_err(|e| GalleryError::ActixError(e.into()))
-        .and_then(|create_job| {
-            match create_job.name.as_ref() {
-                "index_gallery" => Ok(create_job),
-                _ => Err(GalleryError::InvalidForm("\"name\" field value is invalid."))
+        .and_then(move |form| {
+            check_csrf(&form_req, &form.csrf_token)?;
+            if JobRunners::KINDS.contains(&form.name.as_str()) {
+                Ok(CreateJob { name: form.name })
+            } else {
+                Err(GalleryError::InvalidForm("\"name\" field value is invalid."))
             }
         })
         .and_then(move |create_job| {
+            let name = create_job.name.clone();
             state.db.send(create_job)
                 .from_err::<GalleryError>()
                 .flatten()
+                .map(|job_id| (name, job_id))
+        })
+        .then(move |res| {
+            match res {
+                Ok((name, job_id)) => {
+                    job_runners.start(&name, job_id.clone());
+                    Ok((Some(job_id), None))
+                },
+                Err(GalleryError::JobAlreadyRunning) => Ok((None, Some(GalleryError::JobAlreadyRunning.to_string()))),
+                Err(e) => Err(e)
+            }
         })
-        .and_then(move |job_id| {
-            walker_addr.do_send(StartWalking { job_id: job_id.clone() });
+        .and_then(move |(new_job_id, error)| {
             db_addr.send(GetJobs)
                 .from_err::<GalleryError>()
                 .flatten()
-                .join(Ok(job_id))
+                .map(|jobs| (jobs, new_job_id, error))
         })
-        .and_then(|(jobs, new_job_id)| {
+        .and_then(move |(jobs, new_job_id, error)| {
+            let status = if error.is_some() { StatusCode::CONFLICT } else { StatusCode::CREATED };
             let body = future_try!(JobsTemplate {
-                new_job_id: Some(new_job_id),
+                admin: AdminContext::from_request(&req),
+                new_job_id,
+                error,
                 jobs
             }.render().map_err(GalleryInternalError));
-            Box::new(future::ok(HttpResponse::Created().body(body).into()))
+            Box::new(future::ok(HttpResponse::build(status).content_type("text/html").body(body)))
         })
         .responder()
 }
+
+pub fn cancel_job_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let job_id: String = future_try!(req.match_info().query("job_id").map_err(GalleryInternalError));
+    let job_url = format!("/admin/jobs/{}", job_id);
+    let form_req = req.clone();
+
+    req.urlencoded::<CsrfForm>()
+        .map_err(|e| GalleryError::ActixError(e.into()))
+        .and_then(move |form| check_csrf(&form_req, &form.csrf_token))
+        .and_then(move |_| {
+            state.db.send(CancelJob { job_id })
+                .from_err::<GalleryError>()
+                .flatten()
+        })
+        .map(move |_| {
+            HttpResponse::SeeOther()
+                .header(header::LOCATION, job_url)
+                .finish()
+        })
+        .responder()
+}
+
+#[derive(Deserialize)]
+struct ShareForm {
+    path: String,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    expires_in_days: String,
+    /// Checkboxes are only sent when checked.
+    #[serde(default)]
+    allow_download: Option<String>,
+    #[serde(default)]
+    csrf_token: String,
+}
+
+fn render_shares(req: &HttpRequest<AppState>, status: StatusCode, shares: Vec<ShareListing>, new_share: Option<ShareListing>, error: Option<String>)
+    -> Result<HttpResponse, GalleryError>
+{
+    let body = SharesTemplate {
+        admin: AdminContext::from_request(req),
+        new_share,
+        error,
+        shares,
+    }.render().map_err(GalleryInternalError)?;
+    Ok(HttpResponse::build(status).content_type("text/html").body(body))
+}
+
+pub fn get_shares_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    state.db.send(GetActiveShares)
+        .from_err::<GalleryError>()
+        .flatten()
+        .and_then(move |shares| render_shares(&req, StatusCode::OK, shares, None, None))
+        .responder()
+}
+
+pub fn post_shares_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let db_addr = state.db.clone();
+    let form_req = req.clone();
+
+    req.urlencoded::<ShareForm>()
+        .map_err(|e| GalleryError::ActixError(e.into()))
+        .and_then(move |form| {
+            check_csrf(&form_req, &form.csrf_token)?;
+            let expires_at = match form.expires_in_days.trim() {
+                "" => None,
+                days => {
+                    let days: i64 = days.parse()
+                        .map_err(|_| GalleryError::InvalidForm("\"expires_in_days\" field value is invalid."))?;
+                    Some(Utc::now().naive_utc() + Duration::days(days.max(1)))
+                },
+            };
+            Ok(CreateShare {
+                path: PathBuf::from(form.path.trim().trim_matches('/')),
+                label: form.label.trim().to_string(),
+                allow_download: form.allow_download.is_some(),
+                expires_at,
+            })
+        })
+        .and_then(move |create_share| {
+            state.db.send(create_share)
+                .from_err::<GalleryError>()
+                .flatten()
+        })
+        .then(|res| {
+            match res {
+                Ok(new_share) => Ok((StatusCode::CREATED, Some(new_share), None)),
+                Err(GalleryError::NotFound) | Err(GalleryError::InvalidFileName) => {
+                    Ok((StatusCode::NOT_FOUND, None, Some("No album or photo found at this path.".to_string())))
+                },
+                Err(e) => Err(e),
+            }
+        })
+        .and_then(move |(status, new_share, error)| {
+            db_addr.send(GetActiveShares)
+                .from_err::<GalleryError>()
+                .flatten()
+                .and_then(move |shares| render_shares(&req, status, shares, new_share, error))
+        })
+        .responder()
+}
+
+pub fn revoke_share_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let share_id: String = future_try!(req.match_info().query("share_id").map_err(GalleryInternalError));
+    let form_req = req.clone();
+
+    req.urlencoded::<CsrfForm>()
+        .map_err(|e| GalleryError::ActixError(e.into()))
+        .and_then(move |form| check_csrf(&form_req, &form.csrf_token))
+        .and_then(move |_| {
+            state.db.send(RevokeShare { share_id })
+                .from_err::<GalleryError>()
+                .flatten()
+        })
+        .map(|_| {
+            HttpResponse::SeeOther()
+                .header(header::LOCATION, "/admin/shares")
+                .finish()
+        })
+        .responder()
+}
+
+pub fn get_login_route(req: HttpRequest<AppState>) -> LoginTemplate {
+    LoginTemplate {
+        csrf_token: get_csrf_token(&req),
+        error: None,
+    }
+}
+
+pub fn post_login_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let form_req = req.clone();
+    let session_days = state.config.auth.session_days;
+    let client_ip = get_client_ip(&req, &state.config.auth);
+
+    req.urlencoded::<LoginForm>()
+        .map_err(|e| GalleryError::ActixError(e.into()))
+        .and_then(move |form| {
+            check_csrf(&form_req, &form.csrf_token)?;
+            Ok(form)
+        })
+        .and_then(move |form| {
+            // Attempts are refused without checking the password once too
+            // many failed.
+            let username = form.username.clone();
+            let login: Box<Future<Item = Identity, Error = GalleryError>> =
+                if state.login_throttle.is_blocked(client_ip, &username, &state.config.auth) {
+                    Box::new(future::err(GalleryError::TooManyAttempts))
+                } else {
+                    let login = Login { username: form.username, password: form.password, session_days };
+                    Box::new(login.open_session(state.db.clone(), state.hasher.clone()))
+                };
+            login.then(move |res| Ok((username, res)))
+        })
+        .and_then(move |(username, res)| {
+            let state = req.state();
+            match res {
+                Ok(identity) => {
+                    state.login_throttle.reset(&username);
+                    // Users who are not administrators only browse the albums.
+                    let location = if identity.is_admin() { "/admin/jobs" } else { "/" };
+                    let token = identity.session_token.unwrap_or_default();
+                    let mut cookie = build_cookie(SESSION_COOKIE, token, "/", &state.config.auth);
+                    cookie.set_max_age(time::Duration::days(session_days));
+                    Ok(HttpResponse::SeeOther()
+                        .header(header::LOCATION, location)
+                        .cookie(cookie)
+                        .finish())
+                },
+                Err(e @ GalleryError::InvalidCredentials) | Err(e @ GalleryError::TooManyAttempts) => {
+                    let status = if let GalleryError::InvalidCredentials = e {
+                        warn!("Failed login for {} from {:?}.", username, client_ip);
+                        state.login_throttle.record_failure(client_ip, &username, &state.config.auth);
+                        StatusCode::UNAUTHORIZED
+                    } else {
+                        StatusCode::TOO_MANY_REQUESTS
+                    };
+                    let body = LoginTemplate {
+                        csrf_token: get_csrf_token(&req),
+                        error: Some(e.to_string()),
+                    }.render().map_err(GalleryInternalError)?;
+                    Ok(HttpResponse::build(status).content_type("text/html").body(body))
+                },
+                Err(e) => Err(e),
+            }
+        })
+        .responder()
+}
+
+pub fn logout_route((req, state): (HttpRequest<AppState>, State<AppState>))
+    -> Box<Future<Item = HttpResponse, Error = GalleryError>>
+{
+    let form_req = req.clone();
+
+    req.urlencoded::<CsrfForm>()
+        .map_err(|e| GalleryError::ActixError(e.into()))
+        .and_then(move |form| check_csrf(&form_req, &form.csrf_token))
+        .and_then(move |_| {
+            let session_token = req.extensions().get::<Identity>()
+                .and_then(|identity| identity.session_token.clone());
+            let logout: Box<Future<Item = (), Error = GalleryError>> = match session_token {
+                Some(token) => Box::new(state.db.send(Logout { token })
+                    .from_err::<GalleryError>()
+                    .flatten()),
+                None => Box::new(future::ok(())),
+            };
+            logout.map(move |_| {
+                HttpResponse::SeeOther()
+                    .header(header::LOCATION, LOGIN_PATH)
+                    .del_cookie(&build_cookie(SESSION_COOKIE, String::new(), "/", &req.state().config.auth))
+                    .finish()
+            })
+        })
+        .responder()
+}
+
+/// Files served with the validators of `CacheValidators` rather than the
+/// ones derived from their metadata.
+#[derive(Default)]
+struct ValidatedFile;
+
+impl StaticFileConfig for ValidatedFile {
+    fn is_use_etag() -> bool {
+        false
+    }
+
+    fn is_use_last_modifier() -> bool {
+        false
+    }
+}
+
+/// Validators of a response, letting browsers and proxies revalidate their
+/// copy rather than downloading it again.
+struct CacheValidators {
+    etag: header::EntityTag,
+    last_modified: Option<SystemTime>,
+    cache_control: &'static str,
+}
+
+impl CacheValidators {
+    /// If-None-Match takes precedence over If-Modified-Since, see RFC 7232.
+    fn is_fresh(&self, req: &HttpRequest<AppState>) -> bool {
+        match req.get_header::<header::IfNoneMatch>() {
+            Some(header::IfNoneMatch::Any) => true,
+            Some(header::IfNoneMatch::Items(ref etags)) => etags.iter().any(|etag| etag.weak_eq(&self.etag)),
+            None => match (self.last_modified, req.get_header::<header::IfModifiedSince>()) {
+                (Some(modified), Some(header::IfModifiedSince(since))) => header::HttpDate::from(modified) <= since,
+                _ => false,
+            },
+        }
+    }
+
+    /// Answers 304 when the client is up to date, and builds the response
+    /// otherwise.
+    fn respond<F>(self, req: &HttpRequest<AppState>, respond: F) -> Result<HttpResponse, GalleryError>
+    where
+        F: FnOnce() -> Result<HttpResponse, GalleryError>,
+    {
+        let mut res = if self.is_fresh(req) {
+            HttpResponse::NotModified().finish()
+        } else {
+            respond()?
+        };
+
+        let headers = res.headers_mut();
+        if let Ok(etag) = header::HeaderValue::from_str(&self.etag.to_string()) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            if let Ok(last_modified) = header::HeaderValue::from_str(&header::HttpDate::from(last_modified).to_string()) {
+                headers.insert(header::LAST_MODIFIED, last_modified);
+            }
+        }
+        headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static(self.cache_control));
+        Ok(res)
+    }
+}
+
+fn get_body_digest(body: &str) -> String {
+    let digest = Sha256::digest(body.as_bytes());
+    digest.iter().take(16).map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -1,5 +1,7 @@
 use std::fs;
-use std::path::{PathBuf, Path};
+use std::path::{Component, PathBuf, Path};
+
+use sha2::{Sha256, Digest};
 
 use crate::config::Config;
 use crate::error::GalleryError;
@@ -10,6 +12,27 @@ pub fn get_album_canonical_path(album_path: PathBuf, config: &Config) -> PathBuf
     canonical_path
 }
 
+/// Joins a path relative to the storage onto its root, refusing the paths
+/// leading outside of it, including through symbolic links.
+pub fn resolve_storage_path(storage_path: &Path, relative_path: &Path) -> Result<PathBuf, GalleryError> {
+    let root = fs::canonicalize(storage_path)?;
+
+    let mut path = root.clone();
+    for component in relative_path.components() {
+        match component {
+            Component::Normal(segment) => path.push(segment),
+            _ => return Err(GalleryError::NotFound),
+        }
+    }
+
+    let canonical_path = fs::canonicalize(&path)?;
+    if !canonical_path.starts_with(&root) {
+        warn!("Refusing {:?} leading outside of the storage to {:?}", path, canonical_path);
+        return Err(GalleryError::NotFound);
+    }
+    Ok(canonical_path)
+}
+
 pub fn get_file_name_string<P: AsRef<Path>>(path: P) -> Result<String, GalleryError> {
     path.as_ref().file_name()
         .ok_or(GalleryError::InvalidFileName)?
@@ -26,6 +49,77 @@ pub fn trim_one_char(s: String) -> String {
     }
 }
 
+pub fn format_file_size(size: i64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+
+    if size < 1000 {
+        return format!("{} B", size);
+    }
+
+    let mut size = size as f64 / 1000.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = next_unit;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+pub fn format_duration(duration: f64) -> String {
+    let seconds = duration.round() as u64;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Percent-encodes a path relative to the storage to be used in a URL.
+pub fn encode_url_path(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Percent-encodes a value of a query string.
+pub fn encode_query_value(value: &str) -> String {
+    encode_url_path(value).replace('/', "%2F")
+}
+
+/// Compares secrets in a time independent of the position of the first
+/// difference.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// HMAC-SHA256 of the message, see RFC 2104.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        let digest = Sha256::digest(key);
+        block[..digest.len()].copy_from_slice(&digest);
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.input(block.iter().map(|byte| byte ^ 0x36).collect::<Vec<u8>>());
+    inner.input(message);
+
+    let mut outer = Sha256::new();
+    outer.input(block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<u8>>());
+    outer.input(inner.result());
+    outer.result().to_vec()
+}
+
 macro_rules! future_try {
     ($ex:expr) => {
         match $ex {
@@ -34,3 +128,131 @@ macro_rules! future_try {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::os::unix::fs::symlink;
+
+    use actix_web::dev::FromParam;
+
+    use super::*;
+
+    /// Storage directory holding A/photo.jpg, next to a secret.txt file
+    /// that must not be reachable.
+    struct Storage {
+        base: PathBuf,
+        root: PathBuf,
+    }
+
+    impl Storage {
+        fn new() -> Self {
+            let base = env::temp_dir().join(format!("gallery-test-{}", uuid::Uuid::new_v4()));
+            let root = base.join("storage");
+            fs::create_dir_all(root.join("A")).unwrap();
+            fs::write(root.join("A/photo.jpg"), b"photo").unwrap();
+            fs::write(base.join("secret.txt"), b"secret").unwrap();
+            Storage { base, root }
+        }
+
+        fn resolve(&self, relative_path: &str) -> Result<PathBuf, GalleryError> {
+            resolve_storage_path(&self.root, Path::new(relative_path))
+        }
+    }
+
+    impl Drop for Storage {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn is_not_found(result: Result<PathBuf, GalleryError>) -> bool {
+        matches!(result, Err(GalleryError::NotFound))
+    }
+
+    #[test]
+    fn resolves_paths_inside_the_storage() {
+        let storage = Storage::new();
+        let path = storage.resolve("A/photo.jpg").unwrap();
+        assert_eq!(path, fs::canonicalize(storage.root.join("A/photo.jpg")).unwrap());
+    }
+
+    #[test]
+    fn refuses_parent_directories() {
+        let storage = Storage::new();
+        assert!(is_not_found(storage.resolve("../secret.txt")));
+        assert!(is_not_found(storage.resolve("A/../../secret.txt")));
+        assert!(is_not_found(storage.resolve("A/../photo.jpg")));
+    }
+
+    #[test]
+    fn refuses_absolute_paths() {
+        let storage = Storage::new();
+        let secret = storage.base.join("secret.txt");
+        assert!(is_not_found(storage.resolve(secret.to_str().unwrap())));
+    }
+
+    #[test]
+    fn keeps_encoded_separators_literal() {
+        let storage = Storage::new();
+        // Some are already refused by the router, the others must not be
+        // decoded into separators.
+        let cases = [
+            ("..%2Fsecret.txt", true),
+            ("A%2F..%2F..%2Fsecret.txt", false),
+            ("%2e%2e/secret.txt", false),
+            ("A/..%5Csecret.txt", true),
+        ];
+        for &(param, refused_by_router) in &cases {
+            match PathBuf::from_param(param) {
+                Ok(path) => {
+                    assert!(!refused_by_router, "{} was not refused by the router", param);
+                    assert!(is_not_found(resolve_storage_path(&storage.root, &path)), "{} was resolved", param);
+                },
+                Err(_) => assert!(refused_by_router, "{} was refused by the router", param),
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_symlinks_leading_outside() {
+        let storage = Storage::new();
+        symlink(storage.base.join("secret.txt"), storage.root.join("A/link.jpg")).unwrap();
+        symlink(&storage.base, storage.root.join("outside")).unwrap();
+        assert!(is_not_found(storage.resolve("A/link.jpg")));
+        assert!(is_not_found(storage.resolve("outside/secret.txt")));
+    }
+
+    #[test]
+    fn follows_symlinks_inside_the_storage() {
+        let storage = Storage::new();
+        symlink(storage.root.join("A"), storage.root.join("B")).unwrap();
+        let path = storage.resolve("B/photo.jpg").unwrap();
+        assert_eq!(path, fs::canonicalize(storage.root.join("A/photo.jpg")).unwrap());
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn computes_hmac_sha256() {
+        // Test cases 2 and 6 of RFC 4231, the latter with a key longer than
+        // a block.
+        assert_eq!(
+            to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            to_hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+    }
+}
@@ -20,15 +20,11 @@ fn impl_exif_extractor(ast: &DeriveInput) -> TokenStream {
         _ => panic!("ExifExtractor should be derived on struct")
     };
 
-    let fn_extract_exif = gen_extract_exif(fields.clone(), tags.clone());
+    let fn_extract_exif = gen_extract_exif(fields.clone(), tags);
     let fn_get_named_metadata = gen_get_named_metadata(fields, names);
 
     let gen = quote! {
         impl ExifExtractor for #name {
-            const TAG_LIST: &'static [exif::Tag] = &[
-                #( exif::Tag::#tags, )*
-            ];
-
             #fn_extract_exif
             #fn_get_named_metadata
         }
@@ -36,27 +32,33 @@ fn impl_exif_extractor(ast: &DeriveInput) -> TokenStream {
     gen.into()
 }
 
-fn gen_extract_exif(fields: Vec<Ident>, tags: Vec<Ident>) -> proc_macro2::TokenStream {
+fn gen_extract_exif(fields: Vec<Ident>, tags: Vec<proc_macro2::TokenStream>) -> proc_macro2::TokenStream {
     quote! {
         fn extract_exif(&mut self, path: &std::path::PathBuf) -> std::io::Result<()> {
-            let mut exif_map = Self::extract_exif_map(path)?;
-            #( self.#fields = exif_map.remove(&exif::Tag::#tags); )*
+            let reader = Self::read_exif(path)?;
+            #( self.#fields = reader.as_ref().and_then(|reader| Self::get_exif_value(reader, #tags)); )*
             Ok(())
         }
     }
 }
 
-fn gen_get_named_metadata(fields: Vec<Ident>, names: Vec<String>) -> proc_macro2::TokenStream {
+// Fields without a name are extracted but not displayed.
+fn gen_get_named_metadata(fields: Vec<Ident>, names: Vec<Option<String>>) -> proc_macro2::TokenStream {
+    let (fields, names): (Vec<Ident>, Vec<String>) = fields.into_iter()
+        .zip(names)
+        .filter_map(|(field, name)| name.map(|name| (field, name)))
+        .unzip();
+
     quote! {
         fn get_named_metadata(&self) -> Vec<(&str, String)> {
             let mut res = Vec::new();
-            #( res.push(self.#fields.clone().map(|v| (#names, v))); )*
+            #( res.push(self.#fields.as_ref().map(|v| (#names, v.to_string()))); )*
             res.iter().filter_map(|x| x.as_ref()).cloned().collect()
         }
     }
 }
 
-fn find_tags(strukt: &DataStruct) -> (Vec<Ident>, Vec<Ident>, Vec<String>) {
+fn find_tags(strukt: &DataStruct) -> (Vec<Ident>, Vec<proc_macro2::TokenStream>, Vec<Option<String>>) {
     let iter = strukt.fields
         .iter()
         .filter_map(build_tag_field);
@@ -73,7 +75,7 @@ fn find_tags(strukt: &DataStruct) -> (Vec<Ident>, Vec<Ident>, Vec<String>) {
     (fields, tags, names)
 }
 
-fn build_tag_field(field: &Field) -> Option<(Ident, Ident, String)> {
+fn build_tag_field(field: &Field) -> Option<(Ident, proc_macro2::TokenStream, Option<String>)> {
     let mut tag = None;
     let mut name = None;
 
@@ -89,14 +91,22 @@ fn build_tag_field(field: &Field) -> Option<(Ident, Ident, String)> {
         }
     }
 
-    if tag.is_some() && name.is_some() {
-        Some((
-            field.ident.as_ref().unwrap().to_owned(),
-            Ident::new(tag.unwrap().as_str(), Span::call_site()),
-            name.unwrap()
-        ))
+    tag.map(|tag| (
+        field.ident.as_ref().unwrap().to_owned(),
+        gen_tag(&tag),
+        name
+    ))
+}
+
+// Tags unknown to the exif crate are given by their number in the primary
+// image directory, such as "0x9c9e".
+fn gen_tag(tag: &str) -> proc_macro2::TokenStream {
+    if let Some(number) = tag.strip_prefix("0x") {
+        let number = u16::from_str_radix(number, 16).expect("Invalid EXIF tag number");
+        quote! { exif::Tag(exif::Context::Tiff, #number) }
     } else {
-        None
+        let ident = Ident::new(tag, Span::call_site());
+        quote! { exif::Tag::#ident }
     }
 }
 
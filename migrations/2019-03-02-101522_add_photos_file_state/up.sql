@@ -0,0 +1,3 @@
+alter table photos add column file_size bigint not null default 0;
+alter table photos add column file_mtime bigint not null default 0;
+create index photos_hash_idx on photos (hash);
@@ -0,0 +1,13 @@
+drop index job_errors_job_id_idx;
+drop table job_errors;
+
+create table jobs_backup as select id, name, state from jobs;
+drop table jobs;
+create table jobs (
+  id varchar (36) not null,
+  name varchar (32) not null,
+  state varchar (32) not null default "created",
+  primary key (id)
+);
+insert into jobs select * from jobs_backup;
+drop table jobs_backup;
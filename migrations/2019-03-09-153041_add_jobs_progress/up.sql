@@ -0,0 +1,18 @@
+alter table jobs add column started_at datetime;
+alter table jobs add column ended_at datetime;
+alter table jobs add column files_seen integer not null default 0;
+alter table jobs add column files_indexed integer not null default 0;
+alter table jobs add column files_skipped integer not null default 0;
+alter table jobs add column files_failed integer not null default 0;
+alter table jobs add column current_directory text;
+
+create table job_errors (
+  id varchar (36) not null,
+  job_id varchar (36) not null,
+  path text,
+  message text not null,
+  created_at datetime not null,
+  primary key (id),
+  foreign key (job_id) references jobs(id)
+);
+create index job_errors_job_id_idx on job_errors (job_id);
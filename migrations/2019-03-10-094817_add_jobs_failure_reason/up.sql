@@ -0,0 +1 @@
+alter table jobs add column failure_reason text;
@@ -0,0 +1,18 @@
+create table jobs_backup as select id, name, state, started_at, ended_at, files_seen, files_indexed, files_skipped, files_failed, current_directory, failure_reason from jobs;
+drop table jobs;
+create table jobs (
+  id varchar (36) not null,
+  name varchar (32) not null,
+  state varchar (32) not null default 'created',
+  started_at datetime,
+  ended_at datetime,
+  files_seen integer not null default 0,
+  files_indexed integer not null default 0,
+  files_skipped integer not null default 0,
+  files_failed integer not null default 0,
+  current_directory text,
+  failure_reason text,
+  primary key (id)
+);
+insert into jobs select * from jobs_backup;
+drop table jobs_backup;
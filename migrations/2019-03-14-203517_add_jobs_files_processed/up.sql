@@ -0,0 +1 @@
+alter table jobs add column files_processed integer not null default 0;
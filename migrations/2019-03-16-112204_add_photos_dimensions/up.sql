@@ -0,0 +1,5 @@
+alter table photos add column orientation integer;
+alter table photos add column width integer;
+alter table photos add column height integer;
+-- Photos indexed before the versions were recorded are processed again.
+alter table photos add column index_version integer not null default 0;
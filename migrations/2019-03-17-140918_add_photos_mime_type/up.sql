@@ -0,0 +1 @@
+alter table photos add column mime_type varchar (255);
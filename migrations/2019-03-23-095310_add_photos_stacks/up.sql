@@ -0,0 +1,2 @@
+alter table photos add column primary_photo_id varchar (36);
+create index photos_primary_photo_id_idx on photos (primary_photo_id);
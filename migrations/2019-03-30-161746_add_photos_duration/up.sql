@@ -0,0 +1 @@
+alter table photos add column duration double;
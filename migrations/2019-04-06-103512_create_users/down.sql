@@ -0,0 +1,4 @@
+drop index sessions_user_id_idx;
+drop table sessions;
+drop index users_username_idx;
+drop table users;
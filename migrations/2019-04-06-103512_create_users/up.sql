@@ -0,0 +1,17 @@
+create table users (
+  id varchar (36) not null,
+  username varchar (255) not null,
+  password_hash varchar (255) not null,
+  primary key (id)
+);
+create unique index users_username_idx on users (username);
+
+create table sessions (
+  id varchar (64) not null,
+  user_id varchar (36) not null,
+  created_at datetime not null,
+  expires_at datetime not null,
+  primary key (id),
+  foreign key (user_id) references users(id)
+);
+create index sessions_user_id_idx on sessions (user_id);
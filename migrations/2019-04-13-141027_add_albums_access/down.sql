@@ -0,0 +1,14 @@
+drop table user_groups;
+drop table album_grants;
+
+create table albums_backup as select id, name, parent_album_id from albums;
+drop table albums;
+create table albums (
+  id varchar (36) not null,
+  name varchar (255) not null,
+  parent_album_id varchar (36) default null,
+  primary key (id),
+  foreign key (parent_album_id) references albums(id)
+);
+insert into albums select * from albums_backup;
+drop table albums_backup;
@@ -0,0 +1,19 @@
+alter table albums add column access varchar (16) not null default 'public';
+alter table albums add column password_hash varchar (255);
+
+create table album_grants (
+  album_id varchar (36) not null,
+  principal varchar (256) not null,
+  primary key (album_id, principal),
+  foreign key (album_id) references albums(id)
+);
+
+create table user_groups (
+  user_id varchar (36) not null,
+  group_name varchar (255) not null,
+  primary key (user_id, group_name),
+  foreign key (user_id) references users(id)
+);
+
+-- The users created so far could all access the admin pages.
+insert into user_groups select id, 'admin' from users;
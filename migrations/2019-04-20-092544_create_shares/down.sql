@@ -0,0 +1,3 @@
+drop table settings;
+drop index shares_album_id_idx;
+drop table shares;
@@ -0,0 +1,20 @@
+create table shares (
+  id varchar (36) not null,
+  album_id varchar (36) not null,
+  photo_id varchar (36),
+  label varchar (255) not null default '',
+  allow_download boolean not null default 0,
+  created_at datetime not null,
+  expires_at datetime,
+  revoked_at datetime,
+  primary key (id),
+  foreign key (album_id) references albums(id),
+  foreign key (photo_id) references photos(id)
+);
+create index shares_album_id_idx on shares (album_id);
+
+create table settings (
+  name varchar (64) not null,
+  value text not null,
+  primary key (name)
+);
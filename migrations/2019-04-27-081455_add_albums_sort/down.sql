@@ -0,0 +1,13 @@
+create table albums_backup as select id, name, parent_album_id, access, password_hash from albums;
+drop table albums;
+create table albums (
+  id varchar (36) not null,
+  name varchar (255) not null,
+  parent_album_id varchar (36) default null,
+  access varchar (16) not null default 'public',
+  password_hash varchar (255),
+  primary key (id),
+  foreign key (parent_album_id) references albums(id)
+);
+insert into albums select * from albums_backup;
+drop table albums_backup;
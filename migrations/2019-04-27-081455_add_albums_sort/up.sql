@@ -0,0 +1 @@
+alter table albums add column sort varchar (16);
@@ -0,0 +1,39 @@
+drop trigger photos_search_delete;
+drop trigger photos_search_update;
+drop trigger photos_search_insert;
+drop table photos_search;
+drop table photos_search_keys;
+drop view album_paths;
+drop index photos_primary_photo_id_idx;
+drop index photos_hash_idx;
+create table photos_backup as select id, name, album_id, hash, creation_date, camera, exposure_time, aperture, focal_length, focal_length_in_35mm, flash, file_size, file_mtime, orientation, width, height, index_version, mime_type, primary_photo_id, duration from photos;
+drop table photos;
+create table photos (
+  id varchar (36) not null,
+  name varchar (255) not null,
+  album_id varchar (36) not null,
+  hash varchar (256) not null,
+  -- metadata
+  creation_date datetime,
+  camera varchar (60),
+  exposure_time varchar (10),
+  aperture varchar (10),
+  focal_length varchar (10),
+  focal_length_in_35mm varchar (10),
+  flash varchar (255),
+  file_size bigint not null default 0,
+  file_mtime bigint not null default 0,
+  orientation integer,
+  width integer,
+  height integer,
+  index_version integer not null default 0,
+  mime_type varchar (255),
+  primary_photo_id varchar (36),
+  duration double,
+  primary key (id),
+  foreign key (album_id) references photos(id)
+);
+insert into photos select * from photos_backup;
+drop table photos_backup;
+create index photos_hash_idx on photos (hash);
+create index photos_primary_photo_id_idx on photos (primary_photo_id);
@@ -0,0 +1,54 @@
+alter table photos add column caption text;
+alter table photos add column keywords text;
+
+-- The root album has an empty path.
+create view album_paths as
+with recursive paths (album_id, path) as (
+  select id, '' from albums where parent_album_id is null
+  union all
+  select albums.id, case when paths.path = '' then albums.name else paths.path || '/' || albums.name end
+  from albums join paths on albums.parent_album_id = paths.album_id
+)
+select album_id, path from paths;
+
+-- The words of a photo are indexed under an integer key, so that the triggers
+-- find them without scanning the index. Unlike the implicit rowids of the
+-- photos, these keys are kept when the photos table is rebuilt or vacuumed.
+create table photos_search_keys (
+  id integer primary key,
+  photo_id varchar (36) not null unique,
+  foreign key (photo_id) references photos(id)
+);
+insert into photos_search_keys (photo_id) select id from photos;
+
+create virtual table photos_search using fts5 (
+  name,
+  album_path,
+  caption,
+  keywords,
+  tokenize = 'unicode61 remove_diacritics 2'
+);
+insert into photos_search (rowid, name, album_path, caption, keywords)
+  select photos_search_keys.id, photos.name, album_paths.path, photos.caption, photos.keywords
+  from photos
+  join photos_search_keys on photos_search_keys.photo_id = photos.id
+  join album_paths on album_paths.album_id = photos.album_id;
+
+create trigger photos_search_insert after insert on photos begin
+  insert into photos_search_keys (photo_id) values (new.id);
+  insert into photos_search (rowid, name, album_path, caption, keywords)
+    select photos_search_keys.id, new.name, album_paths.path, new.caption, new.keywords
+    from photos_search_keys join album_paths on album_paths.album_id = new.album_id
+    where photos_search_keys.photo_id = new.id;
+end;
+create trigger photos_search_update after update of name, album_id, caption, keywords on photos begin
+  delete from photos_search where rowid = (select id from photos_search_keys where photo_id = old.id);
+  insert into photos_search (rowid, name, album_path, caption, keywords)
+    select photos_search_keys.id, new.name, album_paths.path, new.caption, new.keywords
+    from photos_search_keys join album_paths on album_paths.album_id = new.album_id
+    where photos_search_keys.photo_id = new.id;
+end;
+create trigger photos_search_delete after delete on photos begin
+  delete from photos_search where rowid = (select id from photos_search_keys where photo_id = old.id);
+  delete from photos_search_keys where photo_id = old.id;
+end;
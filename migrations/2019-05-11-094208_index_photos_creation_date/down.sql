@@ -0,0 +1 @@
+drop index photos_creation_date_idx;
@@ -0,0 +1,3 @@
+-- Dates are read as timestamps, the ones SQLite can't read are dropped.
+update photos set creation_date = datetime(creation_date);
+create index photos_creation_date_idx on photos (creation_date);